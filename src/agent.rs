@@ -0,0 +1,86 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::game::*;
+
+pub trait Agent<S: GameState>: Send {
+    fn choose_action(&mut self, state: &S) -> Option<S::Action>;
+
+    // Called for every move played in the game, including the agent's own,
+    // with the state before the move.
+    fn observe(&mut self, _state: &S, _action: &S::Action) {}
+
+    // Called before a new game starts.
+    fn reset(&mut self) {}
+
+    fn name(&self) -> String;
+}
+
+impl<S: GameState> fmt::Debug for dyn Agent<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// For game states that carry their players around and need to be cloned.
+pub type SharedAgent<S> = Arc<Mutex<Box<dyn Agent<S>>>>;
+
+pub fn shared_agent<S: GameState>(agent: impl Agent<S> + 'static
+                                  ) -> SharedAgent<S> {
+    Arc::new(Mutex::new(Box::new(agent)))
+}
+
+// Plays a game to the end with agents indexed by player and returns the
+// final state.
+pub fn play_game<S: GameState>(mut state: S, agents: &mut [Box<dyn Agent<S>>]
+                               ) -> S {
+    for agent in agents.iter_mut() {
+        agent.reset();
+    }
+    while state.rewards().is_none() {
+        let action = match agents[state.player()].choose_action(&state) {
+            Some(action) => action,
+            None => break,
+        };
+        for agent in agents.iter_mut() {
+            agent.observe(&state, &action);
+        }
+        state = state.next_state(&action);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::monte_carlo_agent::*;
+    use crate::random_agent::*;
+    use crate::search_config::*;
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    #[test]
+    fn test_play_game() {
+        let monte_carlo = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(100));
+        let mut agents: Vec<Box<dyn Agent<TicTacToeState>>> = vec![
+            Box::new(RandomAgent::new()), // unused, players start at 1
+            Box::new(monte_carlo),
+            Box::new(RandomAgent::new()),
+        ];
+        let state = play_game(TicTacToeState::new(), &mut agents);
+        assert!(state.rewards().is_some());
+        assert_eq!(agents[1].name(), "MonteCarloAgent");
+    }
+
+    #[test]
+    fn test_shared_agent() {
+        let mut state = TicTacToeState::new();
+        state.players[2] = shared_agent(MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(10)));
+        let clone = state.clone();
+        let mut agent = clone.players[2].lock().unwrap();
+        assert_eq!(agent.name(), "MonteCarloAgent");
+        assert!(agent.choose_action(&state).is_some());
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::game::*;
+use crate::tree::*;
+use crate::tree_node::*;
+
+// What NodeTree::to_dot includes. Nodes are left out with everything below
+// them if they have fewer than min_visits visits or are deeper than
+// max_depth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DotOptions {
+    pub min_visits: f64,
+    pub max_depth: Option<usize>,
+    // Exploration constant of the UCT scores shown, see Ucb1
+    pub c: f64,
+}
+
+impl DotOptions {
+
+    pub fn new() -> Self {
+        Self {
+            min_visits: 0.0,
+            max_depth: None,
+            c: std::f64::consts::SQRT_2,
+        }
+    }
+
+    pub fn min_visits(mut self, min_visits: f64) -> Self {
+        self.min_visits = min_visits;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn c(mut self, c: f64) -> Self {
+        self.c = c;
+        self
+    }
+
+    fn includes(&self, depth: usize, visits: f64) -> bool {
+        visits >= self.min_visits
+            && self.max_depth.map_or(true, |max_depth| depth <= max_depth)
+    }
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn escape(label: String) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<S: GameState> NodeTree<S> {
+
+    // The tree as a Graphviz digraph. Nodes show their action, visits, mean
+    // reward and UCT score, edges are as wide as their share of the root
+    // visits. Links to transpositions are drawn as edges to the node
+    // holding the position, see NodeTree.
+    pub fn to_dot(&self, options: &DotOptions) -> String {
+        let mut out = Vec::new();
+        self.write_dot(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    pub fn write_dot(&self, out: &mut dyn Write, options: &DotOptions
+                     ) -> io::Result<()> {
+        writeln!(out, "digraph tree {{")?;
+        writeln!(out, "    node [shape=box];")?;
+        let root_visits = self.stats(0).plays.max(1.0);
+        let mut order = vec![0 as NodeId];
+        let mut seen = order.iter().cloned().collect::<HashSet<NodeId>>();
+        let mut i = 0;
+        while i < order.len() {
+            let node_id = order[i];
+            self.write_dot_node(out, node_id, options)?;
+            for child_id in self.get(node_id).children() {
+                let edge = self.stats(child_id);
+                if !options.includes(self.get(child_id).depth, edge.plays) {
+                    continue;
+                }
+                let target_id = self.position(child_id);
+                let width = (8.0 * edge.plays / root_visits).max(0.2);
+                writeln!(out, "    n{} -> n{} [penwidth={:.2}];",
+                         node_id, target_id, width)?;
+                if seen.insert(target_id) {
+                    order.push(target_id);
+                }
+            }
+            i += 1;
+        }
+        writeln!(out, "}}")
+    }
+
+    fn write_dot_node(&self, out: &mut dyn Write, node_id: NodeId,
+                      options: &DotOptions) -> io::Result<()> {
+        let node = self.get(node_id);
+        let stats = self.position_stats(node_id);
+        let action = match &node.action {
+            Some(action) => format!("{:?}", action),
+            None => "root".to_string(),
+        };
+        let mut label = format!("{}\\nvisits: {}", escape(action),
+                                stats.plays);
+        // The root has no action to credit wins to
+        if node.parent.is_some() && stats.plays > 0.0 {
+            label += &format!("\\nmean: {:.3}", stats.wins / stats.plays);
+        }
+        if let Some(uct) = self.uct(node_id, options.c) {
+            label += &format!("\\nuct: {:.3}", uct);
+        }
+        writeln!(out, "    n{} [label=\"{}\"];", node_id, label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    fn setup_tree() -> NodeTree<TicTacToeState> {
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        tree.add_node(4, 0); // 1 (node id)
+        tree.add_node(0, 0); // 2
+        tree.add_node(2, 1); // 3
+        for (node_id, wins, plays) in [(0, 0.0, 10.0), (1, 6.0, 8.0),
+                                       (2, 1.0, 2.0), (3, 1.0, 4.0)]
+            .iter() {
+            let stats = tree.stats_mut(*node_id);
+            stats.wins = *wins;
+            stats.plays = *plays;
+        }
+        tree
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let tree = setup_tree();
+        let dot = tree.to_dot(&DotOptions::new().c(0.0));
+        assert!(dot.starts_with("digraph tree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("    n0 [label=\"root\\nvisits: 10\"];\n"));
+        assert!(dot.contains(
+            "    n1 [label=\"4\\nvisits: 8\\nmean: 0.750\\nuct: 0.750\"];\n"));
+        assert!(dot.contains("    n0 -> n1 [penwidth=6.40];\n"));
+        assert!(dot.contains("    n1 -> n3 [penwidth=3.20];\n"));
+    }
+
+    #[test]
+    fn test_to_dot_pruned() {
+        let tree = setup_tree();
+        let dot = tree.to_dot(&DotOptions::new().min_visits(3.0));
+        assert!(!dot.contains("n2"));
+        assert!(dot.contains("n3 [label"));
+        let dot = tree.to_dot(&DotOptions::new().max_depth(1));
+        assert!(dot.contains("n2 [label"));
+        assert!(!dot.contains("n3"));
+    }
+}
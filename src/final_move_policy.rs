@@ -0,0 +1,189 @@
+use rand::seq::SliceRandom;
+use rand::RngCore;
+
+use crate::tree_node::*;
+
+// Picks the move to play once the search is done from the statistics of the
+// root children it may choose from. Returns an index into `children`.
+pub trait FinalMovePolicy: Send + Sync {
+    fn choose(&self, children: &[NodeStats], rng: &mut dyn RngCore
+              ) -> Option<usize>;
+}
+
+fn mean(node: &NodeStats) -> f64 {
+    if node.plays == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    node.wins / node.plays
+}
+
+// A random child among those scoring highest
+fn argmax(children: &[NodeStats], score: impl Fn(&NodeStats) -> f64,
+          mut rng: &mut dyn RngCore) -> Option<usize> {
+    let scores = children.iter().map(score).collect::<Vec<f64>>();
+    let max = scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let best = (0..children.len())
+        .filter(|&i| scores[i] == max)
+        .collect::<Vec<usize>>();
+    best.choose(&mut rng).cloned()
+}
+
+// The robust child: most plays, then most wins
+#[derive(Debug, Clone, Default)]
+pub struct MaxVisits;
+
+impl FinalMovePolicy for MaxVisits {
+    fn choose(&self, children: &[NodeStats], rng: &mut dyn RngCore
+              ) -> Option<usize> {
+        let most_plays = children.iter()
+            .map(|child| child.plays)
+            .fold(f64::NEG_INFINITY, f64::max);
+        argmax(children, |child| {
+            if child.plays == most_plays { child.wins } else { f64::NAN }
+        }, rng)
+    }
+}
+
+// Highest mean reward
+#[derive(Debug, Clone, Default)]
+pub struct MaxValue;
+
+impl FinalMovePolicy for MaxValue {
+    fn choose(&self, children: &[NodeStats], rng: &mut dyn RngCore
+              ) -> Option<usize> {
+        argmax(children, mean, rng)
+    }
+}
+
+// The child with both the most plays and the highest mean. When no child
+// has both, which more search would usually settle, falls back to
+// MaxVisits.
+#[derive(Debug, Clone, Default)]
+pub struct MaxChild;
+
+impl FinalMovePolicy for MaxChild {
+    fn choose(&self, children: &[NodeStats], rng: &mut dyn RngCore
+              ) -> Option<usize> {
+        let most_plays = children.iter()
+            .map(|child| child.plays)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let best_mean = children.iter()
+            .map(mean)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let both = (0..children.len())
+            .filter(|&i| {
+                children[i].plays == most_plays
+                    && mean(&children[i]) == best_mean
+            })
+            .collect::<Vec<usize>>();
+        match both.choose(rng) {
+            Some(&i) => Some(i),
+            None => MaxVisits.choose(children, rng),
+        }
+    }
+}
+
+// Highest lower confidence bound, mean - a / sqrt(n)
+#[derive(Debug, Clone)]
+pub struct SecureChild {
+    pub a: f64,
+}
+
+impl Default for SecureChild {
+    fn default() -> Self {
+        Self {
+            a: 1.0,
+        }
+    }
+}
+
+impl FinalMovePolicy for SecureChild {
+    fn choose(&self, children: &[NodeStats], rng: &mut dyn RngCore
+              ) -> Option<usize> {
+        argmax(children, |child| {
+            mean(child) - self.a / f64::sqrt(child.plays)
+        }, rng)
+    }
+}
+
+// Samples children with probability proportional to n^(1 / temperature),
+// for variety in self-play. Plays the most visited child at temperature 0.
+#[derive(Debug, Clone)]
+pub struct VisitSampling {
+    pub temperature: f64,
+}
+
+impl Default for VisitSampling {
+    fn default() -> Self {
+        Self {
+            temperature: 1.0,
+        }
+    }
+}
+
+impl FinalMovePolicy for VisitSampling {
+    fn choose(&self, children: &[NodeStats], mut rng: &mut dyn RngCore
+              ) -> Option<usize> {
+        if self.temperature == 0.0 {
+            return MaxVisits.choose(children, rng);
+        }
+        // Scale by the most plays so the powers don't overflow
+        let most_plays = children.iter()
+            .map(|child| child.plays)
+            .fold(0.0, f64::max);
+        if most_plays == 0.0 {
+            return (0..children.len()).collect::<Vec<usize>>()
+                .choose(&mut rng).cloned();
+        }
+        (0..children.len()).collect::<Vec<usize>>()
+            .choose_weighted(&mut rng, |&i| {
+                f64::powf(children[i].plays / most_plays,
+                          1.0 / self.temperature)
+            })
+            .ok().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(wins: f64, plays: f64) -> NodeStats {
+        let mut node = NodeStats::new();
+        node.wins = wins;
+        node.plays = plays;
+        node
+    }
+
+    #[test]
+    fn test_final_move_policies() {
+        let rng = &mut rand::thread_rng();
+        let children = vec![node(6.0, 10.0), node(7.0, 10.0), node(3.0, 3.0),
+                            node(0.0, 0.0)];
+        assert_eq!(MaxVisits.choose(&children, rng), Some(1));
+        assert_eq!(MaxValue.choose(&children, rng), Some(2));
+        // No child has both, so the most visited
+        assert_eq!(MaxChild.choose(&children, rng), Some(1));
+        let children = vec![node(6.0, 10.0), node(9.0, 10.0), node(1.0, 3.0)];
+        assert_eq!(MaxChild.choose(&children, rng), Some(1));
+        let children = vec![node(90.0, 100.0), node(2.0, 2.0)];
+        assert_eq!(MaxValue.choose(&children, rng), Some(1));
+        assert_eq!(SecureChild::default().choose(&children, rng), Some(0));
+        assert_eq!(MaxVisits.choose(&[], rng), None);
+    }
+
+    #[test]
+    fn test_visit_sampling() {
+        let rng = &mut rand::thread_rng();
+        let children = vec![node(0.0, 1.0), node(0.0, 9.0), node(0.0, 0.0)];
+        let greedy = VisitSampling { temperature: 0.0 };
+        assert_eq!(greedy.choose(&children, rng), Some(1));
+        let mut counts = [0; 3];
+        for _ in 0..1000 {
+            counts[VisitSampling::default().choose(&children, rng).unwrap()]
+                += 1;
+        }
+        assert_eq!(counts[2], 0);
+        assert!(counts[0] > 30 && counts[0] < 200);
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
 pub type GameAction = (usize, usize); // index, value
 
 #[derive(Debug)]
@@ -7,10 +11,53 @@ pub enum GameResult {
     Draw,
 }
 
-pub trait GameState: std::fmt::Debug + std::clone::Clone {
+pub type ActionMap<S> =
+    HashMap<<S as GameState>::Action, <S as GameState>::Action>;
+
+pub trait GameState: std::fmt::Debug + std::clone::Clone + Send + Sync {
+    type Action: Clone + Eq + Hash + Debug + Send + Sync;
+
     fn new() -> Self;
-    fn reward(&self) -> Option<f64>;
-    fn legal_actions(&self) -> Vec<usize>;
+    // The player to move
+    fn player(&self) -> usize;
+    // Rewards for every player indexed by player, None until the game is over
+    fn rewards(&self) -> Option<Vec<f64>>;
+    fn legal_actions(&self) -> Vec<Self::Action>;
     fn pretty_print(&self);
-    fn next_state(&self, action: usize) -> Self;
+    fn next_state(&self, action: &Self::Action) -> Self;
+
+    // The state on a single line, see TreeFormatter.
+    fn render(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    // Prior probability of choosing `action`, used by Puct.
+    fn prior(&self, _action: &Self::Action) -> f64 {
+        1.0 / self.legal_actions().len() as f64
+    }
+
+    // Identifies the position for transpositions. States with the same hash
+    // are taken to be the same position, None keeps the state to itself.
+    fn hash(&self) -> Option<u64> {
+        None
+    }
+
+    // A symmetric copy of the state standing for all its symmetries, e.g.
+    // rotations of the board, along with a map from the copy's legal
+    // actions to the matching actions in this state. Symmetric states have
+    // to give the same copy. With transpositions, symmetric positions then
+    // share statistics. None if the game has no symmetries to exploit.
+    fn canonical_form(&self) -> Option<(Self, ActionMap<Self>)> {
+        None
+    }
+
+    // Lowest and highest reward a player can get
+    fn reward_bounds(&self) -> (f64, f64) {
+        (0.0, 1.0)
+    }
+
+    // Reward for the player to move
+    fn reward(&self) -> Option<f64> {
+        self.rewards().map(|rewards| rewards[self.player()])
+    }
 }
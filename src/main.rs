@@ -11,17 +11,31 @@ use crate::tic_tac_toe::*;
 use crate::game::*;
 use crate::random_agent::*;
 
+mod agent;
+mod dot;
+mod final_move_policy;
 mod monte_carlo_agent;
 mod tree;
 mod random_agent;
+mod rollout_policy;
+mod search_config;
+mod search_result;
+mod selection_policy;
+#[cfg(feature = "serde")]
+mod snapshot;
 mod game;
 mod tic_tac_toe;
+mod tree_formatter;
 mod tree_node;
 
-mod p;
-
 fn main() {
-    let agent = MonteCarloAgent {};
+    // Search traces are off unless asked for, e.g. with
+    // RUST_LOG=simple_mcts=trace
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .init();
+    let mut agent = MonteCarloAgent::new();
     let mut state = TicTacToeState::new();
     state.board = [1, 1, 0, 0, 0, 0, 2, 2, 0];
 //     let state = TicTacToeState {
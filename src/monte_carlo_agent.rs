@@ -1,151 +1,1835 @@
-use rand::seq::SliceRandom;
-use std::time::Instant;
+use rand::rngs::StdRng;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::{Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, trace, trace_span, Span};
 
+use crate::agent::*;
+use crate::final_move_policy::*;
 use crate::game::*;
+use crate::rollout_policy::*;
+use crate::search_config::*;
+use crate::search_result::*;
+use crate::selection_policy::*;
 use crate::tree::*;
 use crate::tree_node::*;
 
-use crate::p;
+pub type Evaluation<S> = Box<dyn Fn(&S) -> Vec<f64> + Send + Sync>;
 
-pub struct MonteCarloAgent {
+// Moves in the order they were played as (player, action)
+pub type Moves<S> = Vec<(usize, <S as GameState>::Action)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parallelism {
+    Off,
+    // Each of n threads builds its own tree from the root with its own rng.
+    // The statistics of the root children are summed over the trees before
+    // choosing an action. The search limits apply to every tree.
+    Root(usize),
+    // n threads search one shared tree, see virtual_loss. The search limits
+    // apply to the tree as a whole.
+    Tree(usize),
+}
+
+pub struct MonteCarloAgent<S: GameState> {
+    pub config: SearchConfig,
+    pub selection_policy: Box<dyn SelectionPolicy>,
+    pub rollout_policy: Box<dyn RolloutPolicy<S>>,
+    pub final_move_policy: Box<dyn FinalMovePolicy>,
+    // Rollouts stop after this many moves and are scored with evaluation
+    pub rollout_depth: Option<usize>,
+    pub evaluation: Option<Evaluation<S>>,
+    // Keep the tree between searches and continue from the subtree of the
+    // moves played since. Moves have to be reported through observe, and
+    // the game needs a GameState::hash to check they lead to the position
+    // searched next. Without one every search starts over.
+    pub reuse_tree: bool,
+    pub state_cache: StateCache,
+    // Share statistics between nodes with the same GameState::hash, or
+    // symmetric ones with a GameState::canonical_form, see NodeTree
+    pub transpositions: bool,
+    pub parallelism: Parallelism,
+    // With tree parallelism a thread adds this many lost plays to the nodes
+    // on its path until it backs up its result, steering the other threads
+    // towards different branches.
+    pub virtual_loss: f64,
+    // MCTS-Solver: prove wins, losses and draws from terminal states with
+    // minimax backups, avoid proven losses and stop once the root is solved.
+    // Meant for win/loss/draw games, see Proof::from_rewards.
+    pub solver: bool,
+    // Score bounded MCTS: keep pessimistic and optimistic bounds on the
+    // score the root player can get from each node, from terminal states
+    // with minimax backups where every other player minimizes it. Selection
+    // skips children that cannot do better than a sibling.
+    pub score_bounds: bool,
+    // Keep all-moves-as-first statistics: every action played below a node,
+    // in the tree or the rollout, also counts for the child of that node
+    // with the same action and player. Used by the Rave selection policy.
+    pub amaf: bool,
+    // All randomness in the search comes from here, see with_seed
+    rng: Mutex<Box<dyn RngCore + Send>>,
+    tree: Option<NodeTree<S>>,
+    played: Vec<S::Action>,
 }
 
-impl MonteCarloAgent {
+impl<S: GameState> MonteCarloAgent<S> {
+
+    pub fn new() -> Self {
+        Self {
+            config: SearchConfig::default(),
+            selection_policy: Box::new(Ucb1::default()),
+            rollout_policy: Box::new(UniformRandom),
+            final_move_policy: Box::new(MaxVisits),
+            rollout_depth: None,
+            evaluation: None,
+            reuse_tree: false,
+            state_cache: StateCache::Off,
+            transpositions: false,
+            parallelism: Parallelism::Off,
+            virtual_loss: 1.0,
+            solver: false,
+            score_bounds: false,
+            amaf: false,
+            rng: Mutex::new(Box::new(StdRng::from_entropy())),
+            tree: None,
+            played: Vec::new(),
+        }
+    }
+
+    pub fn with_config(mut self, config: SearchConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_selection_policy(mut self,
+                                 policy: impl SelectionPolicy + 'static
+                                 ) -> Self {
+        self.selection_policy = Box::new(policy);
+        self
+    }
 
-    pub fn choose_action(&self, root_state: &impl GameState) -> Option<usize> {
-        let mut tree = NodeTree::new(root_state);
+    pub fn with_rollout_policy(mut self,
+                               policy: impl RolloutPolicy<S> + 'static
+                               ) -> Self {
+        self.rollout_policy = Box::new(policy);
+        self
+    }
+
+    pub fn with_final_move_policy(mut self,
+                                  policy: impl FinalMovePolicy + 'static
+                                  ) -> Self {
+        self.final_move_policy = Box::new(policy);
+        self
+    }
+
+    // Cut rollouts off after `depth` moves and score the state reached with
+    // `evaluation`, which returns rewards like GameState::rewards.
+    pub fn with_rollout_cutoff(mut self, depth: usize,
+                               evaluation: impl Fn(&S) -> Vec<f64> + Send + Sync
+                                   + 'static
+                               ) -> Self {
+        self.rollout_depth = Some(depth);
+        self.evaluation = Some(Box::new(evaluation));
+        self
+    }
+
+    pub fn with_tree_reuse(mut self, reuse_tree: bool) -> Self {
+        self.reuse_tree = reuse_tree;
+        self
+    }
+
+    // Trade memory for speed by keeping node states in the tree, see
+    // StateCache.
+    pub fn with_state_cache(mut self, state_cache: StateCache) -> Self {
+        self.state_cache = state_cache;
+        self
+    }
+
+    pub fn with_transpositions(mut self, transpositions: bool) -> Self {
+        self.transpositions = transpositions;
+        self
+    }
+
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    pub fn with_virtual_loss(mut self, virtual_loss: f64) -> Self {
+        self.virtual_loss = virtual_loss;
+        self
+    }
+
+    pub fn with_solver(mut self, solver: bool) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn with_score_bounds(mut self, score_bounds: bool) -> Self {
+        self.score_bounds = score_bounds;
+        self
+    }
+
+    // Blends AMAF values into the current selection policy, so set that one
+    // first.
+    pub fn with_rave(mut self, schedule: RaveSchedule) -> Self {
+        let policy = mem::replace(&mut self.selection_policy,
+                                  Box::new(Ucb1::default()));
+        self.selection_policy = Box::new(Rave {
+            policy: policy,
+            schedule: schedule,
+        });
+        self.amaf = true;
+        self
+    }
+
+    // Searches with the same seed, config and iteration limit build the same
+    // tree and choose the same action. Time limits make them diverge.
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    pub fn with_rng(mut self, rng: impl RngCore + Send + 'static) -> Self {
+        self.rng = Mutex::new(Box::new(rng));
+        self
+    }
+
+    // The tree the last search left for reuse, see reuse_tree.
+    pub fn tree(&self) -> Option<&NodeTree<S>> {
+        self.tree.as_ref()
+    }
+
+    // Continue the next search from `tree`, e.g. one loaded from a
+    // snapshot, as if the last search had left it.
+    pub fn set_tree(&mut self, tree: NodeTree<S>) {
+        self.tree = Some(tree);
+        self.played.clear();
+    }
+
+    pub fn choose_action(&mut self, root_state: &S) -> Option<S::Action> {
+        self.analyze(root_state).action
+    }
+
+    // Same as choose_action but also reports which limit ended the search.
+    pub fn search(&mut self, root_state: &S
+                  ) -> (Option<S::Action>, StopReason) {
+        let result = self.analyze(root_state);
+        (result.action, result.stop_reason)
+    }
+
+    // Searches like choose_action and reports what the search found along
+    // with the action chosen.
+    pub fn analyze(&mut self, root_state: &S) -> SearchResult<S> {
+        let _search = debug_span!("search", parallelism = ?self.parallelism)
+            .entered();
+        let start = Instant::now();
+        let mut tree = self.reused_tree(root_state)
+            .unwrap_or_else(|| NodeTree::new(root_state));
+        tree.state_cache = self.state_cache;
+        tree.transpositions = self.transpositions;
+        let mut rng = self.rng.lock().unwrap();
+        let rng: &mut dyn RngCore = &mut *rng;
+        let agent = &*self;
+        let mut result = match self.parallelism {
+            Parallelism::Root(threads) if threads > 1 => {
+                let seeds = (1..threads).map(|_| rng.next_u64())
+                    .collect::<Vec<u64>>();
+                let (grown, others) = thread::scope(|scope| {
+                    let handles = seeds.into_iter().map(|seed| {
+                        let search = Span::current();
+                        scope.spawn(move || {
+                            let _search = search.entered();
+                            let mut tree = NodeTree::new(root_state);
+                            tree.state_cache = agent.state_cache;
+                            tree.transpositions = agent.transpositions;
+                            let rng = &mut StdRng::seed_from_u64(seed);
+                            let (_, iterations) =
+                                agent.grow_tree(&mut tree, rng);
+                            (tree, iterations)
+                        })
+                    }).collect::<Vec<_>>();
+                    let grown = agent.grow_tree(&mut tree, rng);
+                    (grown, handles.into_iter()
+                        .map(|handle| handle.join().unwrap())
+                        .collect::<Vec<(NodeTree<S>, usize)>>())
+                });
+                let mut trees = vec![&tree];
+                trees.extend(others.iter().map(|(tree, _)| tree));
+                let merged = Self::merge_roots(root_state, &trees);
+                trace!("merged roots\n{}", merged);
+                // The line is the one of this thread's tree
+                let mut result = self.result(&merged, grown, rng);
+                result.principal_variation =
+                    tree.principal_variation(usize::MAX);
+                result.iterations += others.iter()
+                    .map(|(_, iterations)| iterations)
+                    .sum::<usize>();
+                result.nodes = trees.iter().map(|tree| tree.len()).sum();
+                result.max_depth = trees.iter()
+                    .map(|tree| Self::max_depth(tree))
+                    .max().unwrap();
+                result
+            }
+            Parallelism::Tree(threads) if threads > 1 => {
+                let seeds = (1..threads).map(|_| rng.next_u64())
+                    .collect::<Vec<u64>>();
+                let shared = RwLock::new(tree);
+                let iterations = AtomicUsize::new(0);
+                let now = Instant::now();
+                let stop_reason = thread::scope(|scope| {
+                    let (shared, iterations) = (&shared, &iterations);
+                    for seed in seeds {
+                        let search = Span::current();
+                        scope.spawn(move || {
+                            let _search = search.entered();
+                            let rng = &mut StdRng::seed_from_u64(seed);
+                            agent.grow_shared_tree(shared, iterations, now,
+                                                   rng);
+                        });
+                    }
+                    agent.grow_shared_tree(shared, iterations, now, rng)
+                });
+                tree = shared.into_inner().unwrap();
+                trace!("tree\n{}", tree);
+                // Every thread counted one more iteration before stopping
+                let iterations = iterations.into_inner() - threads;
+                self.result(&tree, (stop_reason, iterations), rng)
+            }
+            _ => {
+                let grown = self.grow_tree(&mut tree, rng);
+                trace!("tree\n{}", tree);
+                self.result(&tree, grown, rng)
+            }
+        };
+        result.elapsed = start.elapsed();
+        debug!(action = ?result.action, iterations = result.iterations,
+               nodes = result.nodes, max_depth = result.max_depth,
+               elapsed = ?result.elapsed, stop_reason = ?result.stop_reason,
+               "search done");
+        if self.reuse_tree {
+            self.tree = Some(tree);
+        }
+        result
+    }
+
+    // The result of searching `tree`, which ran `grown` iterations.
+    fn result(&self, tree: &NodeTree<S>, grown: (StopReason, usize),
+              rng: &mut dyn RngCore) -> SearchResult<S> {
+        let root_state = &tree.root_state;
+        let bounds = root_state.reward_bounds();
+        // Children are in the canonical order with canonical forms
+        let actions = root_state.legal_actions().into_iter()
+            .filter_map(|action| {
+                let tree_action = tree.tree_action(root_state, &action)?;
+                let child_id = tree.find_child(0, &tree_action)?;
+                Some(ActionStats::new(action, &tree.edge_stats(child_id),
+                                      bounds))
+            }).collect();
+        SearchResult {
+            action: self.best_action(0, tree, rng),
+            actions: actions,
+            principal_variation: tree.principal_variation(usize::MAX),
+            iterations: grown.1,
+            nodes: tree.len(),
+            max_depth: Self::max_depth(tree),
+            elapsed: Duration::from_secs(0),
+            stop_reason: grown.0,
+        }
+    }
+
+    fn max_depth(tree: &NodeTree<S>) -> usize {
+        (0..tree.len()).map(|node_id| tree.get(node_id).depth).max().unwrap()
+    }
+
+    // Runs iterations on `tree` until one of the search limits is hit and
+    // returns why it stopped along with the iterations run.
+    fn grow_tree(&self, tree: &mut NodeTree<S>, rng: &mut dyn RngCore
+                 ) -> (StopReason, usize) {
         let root_id = 0 as NodeId;
         let now = Instant::now();
         let mut i = 0;
-        while now.elapsed().as_millis() < 500 {
-            let mut node_id = root_id;
-            let mut state = root_state.clone();
-            state.pretty_print();
-            node_id = self.selection(root_id, &tree, &state);
-            state = state.next_state(tree.get(node_id).action);
-            state.pretty_print();
-            if !state.legal_actions().is_empty() && state.reward().is_none() {
-                node_id = self.expansion(node_id, &mut tree, &state);
-                state = state.next_state(tree.get(node_id).action);
-                state = self.simulate(state);
-            }
-            state.pretty_print();
-            p!(state.reward());
-            p!("-------");
-            self.back_prop(node_id, &mut tree, &state);
-            if i > 14 {
-                break;
+        loop {
+            if self.is_solved(tree) {
+                return (StopReason::Solved, i);
             }
+            if let Some(reason) = self.config.stop_reason(
+                now.elapsed(), i, tree.len()) {
+                return (reason, i);
+            }
+            let _iteration = trace_span!("iteration", i).entered();
+            let (mut path, mut state) = trace_span!("selection").in_scope(|| {
+                self.selection(root_id, tree, &tree.root_state, rng)
+            });
+            let node_id = *path.last().unwrap();
+            trace!(node_id, state = %state.render(), "selected");
+            // Leaves are simulated once before they get children
+            if !state.legal_actions().is_empty() && state.rewards().is_none()
+                && (node_id == root_id || tree.stats(node_id).plays > 0.0) {
+                let _expansion = trace_span!("expansion").entered();
+                let child_id = self.expansion(node_id, tree, &state, rng);
+                path.push(child_id);
+                state = Self::child_state(tree, child_id, &state,
+                                          Self::is_own_line(tree, &path));
+                trace!(child_id, "expanded");
+            }
+            let (rewards, weight, moves) = match state.rewards() {
+                Some(rewards) => {
+                    self.settle(&path, tree, &rewards);
+                    (Some(rewards), 1.0, Vec::new())
+                }
+                None => trace_span!("simulation").in_scope(|| {
+                    self.simulate_leaf(state, rng)
+                }),
+            };
+            trace!(?rewards, weight, "simulated");
+            trace_span!("backprop").in_scope(|| {
+                self.back_prop(&path, tree, rewards.as_deref(), weight);
+                if let (true, Some(rewards)) = (self.amaf, &rewards) {
+                    self.back_prop_amaf(&path, tree, rewards, weight, &moves);
+                }
+            });
             i += 1;
         }
-        tree.pretty_print();
-        self.best_action(0 as NodeId, &tree)
     }
 
-    fn best_action<S: GameState>(&self, node_id: NodeId,
-                                 tree: &NodeTree<S>
-                                 ) -> Option<usize> {
-        let mut most_plays = f64::NEG_INFINITY;
-        let mut best_wins = f64::NEG_INFINITY;
-        let mut best_actions = Vec::new();
-        for child_id in tree.children(node_id) {
-            let child = tree.get(child_id);
-            if child.plays > most_plays {
-                most_plays = child.plays;
-                best_actions = vec![child.action];
-                best_wins = child.wins;
-            } else if child.plays == most_plays {
-                if child.wins > best_wins {
-                    best_wins = child.wins;
-                    best_actions = vec![child.action];
-                } else if child.wins == best_wins {
-                    best_actions.push(child.action);
+    // Runs iterations on a tree shared with other threads until one of the
+    // search limits is hit. `iterations` counts the iterations started by
+    // all threads since `start`.
+    fn grow_shared_tree(&self, shared: &RwLock<NodeTree<S>>,
+                        iterations: &AtomicUsize, start: Instant,
+                        rng: &mut dyn RngCore) -> StopReason {
+        let root_id = 0 as NodeId;
+        loop {
+            let i = iterations.fetch_add(1, Ordering::Relaxed);
+            let len = shared.read().unwrap().len();
+            if self.is_solved(&shared.read().unwrap()) {
+                return StopReason::Solved;
+            }
+            if let Some(reason) = self.config.stop_reason(
+                start.elapsed(), i, len) {
+                return reason;
+            }
+            let _iteration = trace_span!("iteration", i).entered();
+            let (mut path, mut state) = trace_span!("selection").in_scope(|| {
+                let tree = shared.read().unwrap();
+                let (path, state) =
+                    self.selection(root_id, &tree, &tree.root_state, rng);
+                self.add_virtual_loss(&path, &tree, self.virtual_loss);
+                (path, state)
+            });
+            let node_id = *path.last().unwrap();
+            trace!(node_id, state = %state.render(), "selected");
+            if !state.legal_actions().is_empty() && state.rewards().is_none() {
+                let needs_children = node_id == root_id
+                    || shared.read().unwrap().stats(node_id).plays
+                        > self.virtual_loss;
+                if needs_children {
+                    let _expansion = trace_span!("expansion").entered();
+                    // Children are worked out with read access and only
+                    // added under the write lock. Another thread may have
+                    // expanded the node in the meantime, it's then kept.
+                    let expansion = {
+                        let tree = shared.read().unwrap();
+                        if tree.has_children(node_id) {
+                            None
+                        } else {
+                            Some(tree.prepare_expansion(node_id, &state))
+                        }
+                    };
+                    let child_id = expansion.and_then(|expansion| {
+                        let mut tree = shared.write().unwrap();
+                        let child_id = tree.publish_expansion(expansion)?
+                            .choose(rng)?;
+                        tree.stats_mut(child_id).plays += self.virtual_loss;
+                        Some(child_id)
+                    });
+                    if let Some(child_id) = child_id {
+                        let tree = shared.read().unwrap();
+                        path.push(child_id);
+                        state = Self::child_state(
+                            &tree, child_id, &state,
+                            Self::is_own_line(&tree, &path));
+                        trace!(child_id, "expanded");
+                    }
                 }
             }
+            let terminal = state.rewards();
+            let (rewards, weight, moves) = match terminal.clone() {
+                Some(rewards) => (Some(rewards), 1.0, Vec::new()),
+                None => trace_span!("simulation").in_scope(|| {
+                    self.simulate_leaf(state, rng)
+                }),
+            };
+            trace!(?rewards, weight, "simulated");
+            let _backprop = trace_span!("backprop").entered();
+            let tree = shared.read().unwrap();
+            if let Some(rewards) = terminal {
+                self.settle(&path, &tree, &rewards);
+            }
+            self.add_virtual_loss(&path, &tree, -self.virtual_loss);
+            self.back_prop(&path, &tree, rewards.as_deref(), weight);
+            if let (true, Some(rewards)) = (self.amaf, &rewards) {
+                self.back_prop_amaf(&path, &tree, rewards, weight, &moves);
+            }
         }
-        best_actions.choose(&mut rand::thread_rng()).copied()
     }
 
-    fn back_prop<S: GameState>(&self, mut node_id: NodeId,
-                               tree: &mut NodeTree<S>,
-                               state: &S) {
-        // Collect ancestor node_ids
-        let mut node_ids = vec![];
-        while let Some(par_id) = tree.get(node_id).parent {
-            node_ids.push(node_id);
-            node_id = par_id;
+    // Adds `loss` plays without wins to the nodes on `path`.
+    fn add_virtual_loss(&self, path: &[NodeId], tree: &NodeTree<S>,
+                        loss: f64) {
+        for &node_id in path {
+            tree.update_stats(node_id, |stats| stats.plays += loss);
         }
-        // Then replay them
+    }
+
+    // A tree holding only the root and its children, with the statistics of
+    // the root children of all `trees` summed by action.
+    fn merge_roots(root_state: &S, trees: &[&NodeTree<S>]) -> NodeTree<S> {
+        let mut merged = NodeTree::new(root_state);
+        merged.transpositions = trees[0].transpositions;
+        merged.expand(0, root_state);
+        for tree in trees {
+            merged.stats_mut(0).plays += tree.stats(0).plays;
+            for child_id in tree.children(0) {
+                let action = tree.get(child_id).action.as_ref().unwrap();
+                let merged_id = match merged.find_child(0, action) {
+                    Some(merged_id) => merged_id,
+                    None => continue,
+                };
+                let stats = tree.stats(child_id).clone();
+                let settled = tree.stats(tree.position(child_id));
+                let merged_stats = merged.stats_mut(merged_id);
+                merged_stats.wins += stats.wins;
+                merged_stats.sq_wins += stats.sq_wins;
+                merged_stats.plays += stats.plays;
+                if settled.proof != Proof::Unknown {
+                    merged_stats.proof = settled.proof;
+                }
+                merged_stats.pessimistic =
+                    merged_stats.pessimistic.max(settled.pessimistic);
+                merged_stats.optimistic =
+                    merged_stats.optimistic.min(settled.optimistic);
+            }
+        }
+        merged
+    }
+
+    // Follows the moves observed since the last search down the previous
+    // tree and makes the node reached the new root. Returns None when there
+    // is no previous tree, a move was never explored or the moves don't lead
+    // to `root_state`, e.g. because some weren't observed. States are
+    // compared by hash, so games without one never reuse their tree.
+    fn reused_tree(&mut self, root_state: &S) -> Option<NodeTree<S>> {
+        let played = mem::take(&mut self.played);
+        let mut tree = self.tree.take()?;
+        let mut node_id = 0 as NodeId;
         let mut state = tree.root_state.clone();
-        for node_id in node_ids.iter().rev() {
-            let node = tree.get_mut(*node_id);
-            p!(state.reward());
-            if let Some(score) = state.reward() {
-                node.wins += score;
+        for action in played.iter() {
+            let action = tree.tree_action(&state, action)?;
+            node_id = tree.find_child(node_id, &action)?;
+            state = tree.next_state(&state, &action);
+        }
+        if state.hash().is_none() || state.hash() != root_state.hash() {
+            return None;
+        }
+        let root_player = tree.root_state.player();
+        tree.reroot(node_id);
+        tree.root_state = root_state.clone();
+        // Score bounds are for the old root player
+        if root_state.player() != root_player {
+            for node_id in 0..tree.len() {
+                let stats = tree.stats_mut(node_id);
+                stats.pessimistic = f64::NEG_INFINITY;
+                stats.optimistic = f64::INFINITY;
             }
-            node.plays += 1.0;
-            state = state.next_state(node.action);
         }
+        Some(tree)
     }
 
-    fn simulate<S: GameState>(&self, mut state: S) -> S {
-        let mut actions = state.legal_actions();
-        while !actions.is_empty() {
-            let action = *actions.choose(&mut rand::thread_rng()).unwrap();
-            state = state.next_state(action);
-            actions = state.legal_actions();
+    // The action to play in the state of node_id, in that state's
+    // orientation, see NodeTree::game_action. A random legal action if the
+    // search stopped before node_id got children.
+    fn best_action(&self, node_id: NodeId, tree: &NodeTree<S>,
+                   mut rng: &mut dyn RngCore) -> Option<S::Action> {
+        let state = tree.state(node_id);
+        if !tree.has_children(node_id) {
+            return state.legal_actions().choose(&mut rng).cloned();
+        }
+        let proof = |child_id| {
+            let player = tree.get(child_id).player;
+            let proof = tree.stats(tree.position(child_id)).proof;
+            (proof.is_win_for(player), proof.is_loss_for(player))
+        };
+        let mut candidates = tree.children(node_id).collect::<Vec<NodeId>>();
+        if self.solver {
+            if let Some(&child_id) = candidates.iter()
+                .find(|&&child_id| proof(child_id).0) {
+                let action = tree.get(child_id).action.as_ref().unwrap();
+                return Some(tree.game_action(&state, action));
+            }
+            // Avoid proven losses and keep a proven draw
+            candidates = Self::narrow(candidates, |child_id| {
+                !proof(child_id).1
+            });
+            if tree.stats(node_id).proof == Proof::Draw {
+                candidates = Self::narrow(candidates, |child_id| {
+                    tree.stats(tree.position(child_id)).proof == Proof::Draw
+                });
+            }
+        }
+        if self.score_bounds {
+            let parent = tree.stats(node_id);
+            let root_player = tree.root_state.player();
+            let (min, max) = tree.root_state.reward_bounds();
+            let settled = |child_id| tree.stats(tree.position(child_id));
+            let maximizes = |child_id| tree.get(child_id).player == root_player;
+            // Skip children a sibling is known to beat, like selection
+            candidates = Self::narrow(candidates, |child_id| {
+                !Self::is_pruned(&parent, &settled(child_id),
+                                 maximizes(child_id))
+            });
+            // Once the bounds meet play a child that secures the value
+            if parent.pessimistic >= parent.optimistic {
+                candidates = Self::narrow(candidates, |child_id| {
+                    let child = settled(child_id);
+                    if maximizes(child_id) {
+                        child.pessimistic.max(min) >= parent.pessimistic
+                    } else {
+                        child.optimistic.min(max) <= parent.optimistic
+                    }
+                });
+            }
         }
-        state
+        let stats = candidates.iter()
+            .map(|&child_id| tree.edge_stats(child_id))
+            .collect::<Vec<NodeStats>>();
+        let i = self.final_move_policy.choose(&stats, rng)?;
+        let action = tree.get(candidates[i]).action.as_ref().unwrap();
+        Some(tree.game_action(&state, action))
     }
 
-    fn expansion<S: GameState>(&self, mut node_id: NodeId,
-                               tree: &mut NodeTree<S>,
-                               state: &S
-                               ) -> NodeId {
-        if tree.is_expanded(node_id) {
-            return node_id;
+    // The candidates passing `keep`, or all of them if none do.
+    fn narrow(candidates: Vec<NodeId>, keep: impl Fn(NodeId) -> bool
+              ) -> Vec<NodeId> {
+        let kept = candidates.iter().cloned()
+            .filter(|&child_id| keep(child_id))
+            .collect::<Vec<NodeId>>();
+        if kept.is_empty() {
+            candidates
+        } else {
+            kept
         }
-        let actions = tree.unexpanded_actions(node_id, state);
-        let action = *actions.choose(&mut rand::thread_rng()).unwrap();
-        let node = tree.get_mut(node_id);
-//         if actions.len() == 1 {
-//             node.status = NodeStatus::Expanded;
-//         }
-        let child_id = tree.add_node(action, node_id);
-        child_id
-    }
-
-    pub fn selection<S: GameState>(&self, mut node_id: NodeId,
-                                   tree: &NodeTree<S>,
-                                   state: &S) -> NodeId {
-        let mut node = tree.get(node_id);
-        while tree.is_expanded(node_id) && !state.legal_actions().is_empty() {
-            let child_ids = tree.children(node_id).collect::<Vec<NodeId>>();
-            let mut max = (child_ids[0], f64::NEG_INFINITY); // (NodeId, uct)
-            for child_id in child_ids {
+    }
+
+    // Credits every node on `path`, which starts at the root, with the
+    // reward of the player who took its action, counted `weight` times. With
+    // transpositions the positions the nodes lead to are credited as well.
+    fn back_prop(&self, path: &[NodeId], tree: &NodeTree<S>,
+                 rewards: Option<&[f64]>, weight: f64) {
+        let credit = |stats: &mut NodeStats, player: usize| {
+            if let Some(rewards) = rewards {
+                let score = rewards[player];
+                stats.wins += weight * score;
+                stats.sq_wins += weight * score * score;
+            }
+            stats.plays += weight;
+        };
+        tree.update_stats(path[0], |stats| stats.plays += weight);
+        tree.update_position_stats(path[0], |stats| stats.plays += weight);
+        for &node_id in &path[1..] {
+            let player = tree.get(node_id).player;
+            tree.update_stats(node_id, |stats| credit(stats, player));
+            let player = tree.get(tree.position(node_id)).player;
+            tree.update_position_stats(node_id, |stats| credit(stats, player));
+        }
+    }
+
+    fn is_solved(&self, tree: &NodeTree<S>) -> bool {
+        let stats = tree.stats(0);
+        (self.solver && stats.proof != Proof::Unknown)
+            || (self.score_bounds && stats.pessimistic >= stats.optimistic)
+    }
+
+    // Records what reaching a terminal node, the last on `path`, tells the
+    // solver and the score bounds.
+    fn settle(&self, path: &[NodeId], tree: &NodeTree<S>, rewards: &[f64]) {
+        if self.solver {
+            self.prove(path, tree, rewards);
+        }
+        if self.score_bounds {
+            let score = rewards[tree.root_state.player()];
+            self.bound(path, tree, score);
+        }
+    }
+
+    // Sets both bounds of the last node on `path`, whose state is terminal,
+    // to `score` and tightens the bounds of the nodes above it. Children
+    // without bounds yet are taken to be within GameState::reward_bounds.
+    fn bound(&self, path: &[NodeId], tree: &NodeTree<S>, score: f64) {
+        let set_bounds = |node_id, bounds: (f64, f64)| {
+            for id in [node_id, tree.position(node_id)] {
+                tree.update_stats(id, |stats| {
+                    stats.pessimistic = bounds.0;
+                    stats.optimistic = bounds.1;
+                });
+            }
+        };
+        let (&node_id, ancestors) = path.split_last().unwrap();
+        set_bounds(node_id, (score, score));
+        let root_player = tree.root_state.player();
+        let (min, max) = tree.root_state.reward_bounds();
+        for &node_id in ancestors.iter().rev() {
+            let maximizes = tree.get(tree.children(node_id).start).player
+                == root_player;
+            let mut bounds = if maximizes {
+                (f64::NEG_INFINITY, f64::NEG_INFINITY)
+            } else {
+                (f64::INFINITY, f64::INFINITY)
+            };
+            for child_id in tree.children(node_id) {
+                let stats = tree.stats(tree.position(child_id));
+                let pessimistic = stats.pessimistic.max(min);
+                let optimistic = stats.optimistic.min(max);
+                bounds = if maximizes {
+                    (bounds.0.max(pessimistic), bounds.1.max(optimistic))
+                } else {
+                    (bounds.0.min(pessimistic), bounds.1.min(optimistic))
+                };
+            }
+            let stats = tree.stats(node_id);
+            if (stats.pessimistic, stats.optimistic) == bounds {
+                return;
+            }
+            set_bounds(node_id, bounds);
+        }
+    }
+
+    // Updates the AMAF statistics of the children of every node on `path`.
+    // `moves` are the moves played in the rollout from its last node. Tree
+    // actions are compared as played in the states along the path, since
+    // with canonical forms every node has its own orientation.
+    fn back_prop_amaf(&self, path: &[NodeId], tree: &NodeTree<S>,
+                      rewards: &[f64], weight: f64, moves: &Moves<S>) {
+        let mut states = vec![tree.state(path[0])];
+        for &node_id in &path[1..] {
+            let action = tree.get(node_id).action.as_ref().unwrap();
+            states.push(tree.next_state(states.last().unwrap(), action));
+        }
+        let mut played = moves.iter().cloned().collect::<HashSet<_>>();
+        for (i, pair) in path.windows(2).enumerate().rev() {
+            let (parent_id, node) = (pair[0], tree.get(pair[1]));
+            let state = &states[i];
+            let game_action = |node: &Node<S::Action>| {
+                tree.game_action(state, node.action.as_ref().unwrap())
+            };
+            played.insert((node.player, game_action(node)));
+            for child_id in tree.children(parent_id) {
                 let child = tree.get(child_id);
-                // UCT is NaN when plays is zero but that's ok as long as
-                // something is chosen.
-                let uct = (child.wins / child.plays) +
-                    f64::sqrt(2.0 * f64::ln(node.plays) / child.plays);
-                if uct > max.1 {
-                    max = (child_id, uct);
+                let key = (child.player, game_action(child));
+                if played.contains(&key) {
+                    let score = rewards[child.player];
+                    tree.update_stats(child_id, |stats| {
+                        stats.amaf_wins += weight * score;
+                        stats.amaf_plays += weight;
+                    });
                 }
             }
+        }
+    }
+
+    // Marks the last node on `path`, whose state is terminal with `rewards`,
+    // as proven and works out which nodes above it are proven by that. A
+    // node is won for its player to move if one child is, otherwise it is
+    // proven once all children are, as a draw if any child is. Proofs are
+    // kept on link targets too, as they hold for the position.
+    fn prove(&self, path: &[NodeId], tree: &NodeTree<S>, rewards: &[f64]) {
+        let set_proof = |node_id, proof| {
+            tree.update_stats(node_id, |stats| stats.proof = proof);
+            tree.update_stats(tree.position(node_id),
+                              |stats| stats.proof = proof);
+        };
+        let (&node_id, ancestors) = path.split_last().unwrap();
+        set_proof(node_id, Proof::from_rewards(rewards));
+        for &node_id in ancestors.iter().rev() {
+            let proof = Self::children_proof(node_id, tree);
+            if proof == Proof::Unknown {
+                return;
+            }
+            set_proof(node_id, proof);
+        }
+    }
+
+    fn children_proof(node_id: NodeId, tree: &NodeTree<S>) -> Proof {
+        let mut all_proven = tree.has_children(node_id);
+        let mut draw = false;
+        let mut loss = Proof::Unknown;
+        for child_id in tree.children(node_id) {
+            let player = tree.get(child_id).player;
+            match tree.stats(tree.position(child_id)).proof {
+                proof if proof.is_win_for(player) => return proof,
+                Proof::Unknown => all_proven = false,
+                Proof::Draw => draw = true,
+                proof => loss = proof,
+            }
+        }
+        match (all_proven, draw) {
+            (false, _) => Proof::Unknown,
+            (true, true) => Proof::Draw,
+            (true, false) => loss,
+        }
+    }
+
+    // Runs config.leaf_rollouts rollouts from `state` on the thread pool and
+    // returns their mean rewards along with the number of rollouts. Rollouts
+    // that end without rewards are left out of the mean. The moves returned
+    // are those of the first rollout with rewards.
+    fn simulate_leaf(&self, state: S, rng: &mut dyn RngCore
+                     ) -> (Option<Vec<f64>>, f64, Moves<S>) {
+        let rollouts = self.config.leaf_rollouts;
+        if rollouts <= 1 {
+            let (rewards, moves) = self.simulate(state, rng);
+            return (rewards, 1.0, moves);
+        }
+        let seeds = (0..rollouts).map(|_| rng.next_u64())
+            .collect::<Vec<u64>>();
+        let mut results = seeds.into_par_iter()
+            .filter_map(|seed| {
+                let rng = &mut StdRng::seed_from_u64(seed);
+                match self.simulate(state.clone(), rng) {
+                    (Some(rewards), moves) => Some((rewards, moves)),
+                    (None, _) => None,
+                }
+            })
+            .collect::<Vec<(Vec<f64>, Moves<S>)>>();
+        let mut mean = match results.first() {
+            Some((rewards, _)) => vec![0.0; rewards.len()],
+            None => return (None, rollouts as f64, Vec::new()),
+        };
+        for (rewards, _) in results.iter() {
+            for (total, reward) in mean.iter_mut().zip(rewards) {
+                *total += reward / results.len() as f64;
+            }
+        }
+        let moves = results.swap_remove(0).1;
+        (Some(mean), rollouts as f64, moves)
+    }
+
+    // Plays the rollout policy from `state` and returns the rewards at the
+    // end, either from a terminal state or from the evaluation at the
+    // cutoff depth. The moves played are only recorded for AMAF.
+    fn simulate(&self, mut state: S, rng: &mut dyn RngCore
+                ) -> (Option<Vec<f64>>, Moves<S>) {
+        let mut depth = 0;
+        let mut moves = Vec::new();
+        while state.rewards().is_none() {
+            if let (Some(max_depth), Some(evaluation)) =
+                (self.rollout_depth, &self.evaluation) {
+                if depth >= max_depth {
+                    return (Some(evaluation(&state)), moves);
+                }
+            }
+            match self.rollout_policy.choose_action(&state, rng) {
+                Some(action) => {
+                    if self.amaf {
+                        moves.push((state.player(), action.clone()));
+                    }
+                    state = state.next_state(&action);
+                }
+                None => break,
+            }
+            depth += 1;
+        }
+        (state.rewards(), moves)
+    }
+
+    // Adds all children of node_id and returns a random one to simulate.
+    fn expansion(&self, node_id: NodeId, tree: &mut NodeTree<S>, state: &S,
+                 mut rng: &mut dyn RngCore) -> NodeId {
+        if tree.has_children(node_id) {
+            return node_id;
+        }
+        let children = tree.expand(node_id, state);
+        children.choose(&mut rng).unwrap_or(node_id)
+    }
+
+    // Descends from node_id, whose state is `state`, to a node without
+    // children and returns it along with its state. Ties between children
+    // are broken at random.
+    pub fn selection(&self, mut node_id: NodeId, tree: &NodeTree<S>,
+                     state: &S, rng: &mut dyn RngCore) -> (Vec<NodeId>, S) {
+        let mut state = state.clone();
+        let mut path = vec![node_id];
+        let mut own_line = true;
+        let root_player = tree.root_state.player();
+        while tree.has_children(node_id) {
+            let parent = &tree.position_stats(node_id);
+            let settled_parent = &tree.stats(node_id);
+            let mut max = (node_id, f64::NEG_INFINITY); // (NodeId, score)
+            let mut ties = 0;
+            for child_id in tree.children(node_id) {
+                let settled = tree.stats(tree.position(child_id));
+                // Proven children are settled, search the others. If all
+                // children are proven so is the node, but another thread may
+                // not have marked it yet.
+                let score = if self.solver && settled.proof != Proof::Unknown {
+                    f64::NEG_INFINITY
+                } else if self.score_bounds && Self::is_pruned(
+                    settled_parent, &settled,
+                    tree.get(child_id).player == root_player) {
+                    f64::NEG_INFINITY
+                } else {
+                    let stats = tree.edge_stats(child_id);
+                    self.selection_policy.score(parent, &stats, rng)
+                };
+                if score > max.1 {
+                    max = (child_id, score);
+                    ties = 1;
+                } else if score == max.1 {
+                    ties += 1;
+                    if rng.gen_range(0, ties) == 0 {
+                        max = (child_id, score);
+                    }
+                }
+            }
+            own_line &= tree.get(max.0).parent == Some(node_id);
             node_id = max.0;
-            node = tree.get(node_id);
+            state = Self::child_state(tree, node_id, &state, own_line);
+            path.push(node_id);
+        }
+        (path, state)
+    }
+
+    // The state of child_id reached from a node with `state`. Cached states
+    // are in the orientation of the child's own line of parents, so they
+    // are only used if `state` is too, which `own_line` tells.
+    fn child_state(tree: &NodeTree<S>, child_id: NodeId, state: &S,
+                   own_line: bool) -> S {
+        match tree.cached_state(child_id) {
+            Some(cached) if own_line => cached.clone(),
+            _ => tree.next_state(state,
+                                 tree.get(child_id).action.as_ref().unwrap()),
+        }
+    }
+
+    // Whether `path` only follows parents to children, not links.
+    fn is_own_line(tree: &NodeTree<S>, path: &[NodeId]) -> bool {
+        path.windows(2).all(|pair| tree.get(pair[1]).parent == Some(pair[0]))
+    }
+
+    // Whether a child cannot do better than one of its siblings for the
+    // player choosing between them.
+    fn is_pruned(parent: &NodeStats, child: &NodeStats, maximizes: bool
+                 ) -> bool {
+        if maximizes {
+            child.optimistic < parent.pessimistic
+        } else {
+            child.pessimistic > parent.optimistic
+        }
+    }
+}
+
+impl<S: GameState> Agent<S> for MonteCarloAgent<S> {
+    fn choose_action(&mut self, state: &S) -> Option<S::Action> {
+        MonteCarloAgent::choose_action(self, state)
+    }
+
+    fn observe(&mut self, _state: &S, action: &S::Action) {
+        if self.tree.is_some() {
+            self.played.push(action.clone());
         }
-        node_id
+    }
+
+    fn reset(&mut self) {
+        self.tree = None;
+        self.played.clear();
+    }
+
+    fn name(&self) -> String {
+        "MonteCarloAgent".to_string()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use crate::random_agent::*;
     use crate::tic_tac_toe::*;
     use crate::tree::*;
 
     use super::*;
 
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct Take(pub usize);
+
+    // Players take one to three stones, whoever takes the last one wins.
+    #[derive(Debug, Clone)]
+    pub struct NimState {
+        pub stones: usize,
+        pub player: usize,
+    }
+
+    impl GameState for NimState {
+        type Action = Take;
+
+        fn new() -> Self {
+            Self {
+                stones: 5,
+                player: 0,
+            }
+        }
+
+        fn player(&self) -> usize {
+            self.player
+        }
+
+        fn rewards(&self) -> Option<Vec<f64>> {
+            if self.stones > 0 {
+                return None;
+            }
+            let mut rewards = vec![1.0, 1.0];
+            rewards[self.player] = 0.0;
+            Some(rewards)
+        }
+
+        fn legal_actions(&self) -> Vec<Take> {
+            (1..=self.stones.min(3)).map(Take).collect()
+        }
+
+        fn pretty_print(&self) {
+            println!("stones: {} player: {}", self.stones, self.player);
+        }
+
+        fn next_state(&self, action: &Take) -> Self {
+            Self {
+                stones: self.stones - action.0,
+                player: 1 - self.player,
+            }
+        }
+    }
+
+    // Players take turns adding 0 to 3 to a total, four turns in all. Player
+    // 0 gets total / 12 and player 1 the rest.
+    #[derive(Debug, Clone)]
+    pub struct SumState {
+        pub total: usize,
+        pub turn: usize,
+    }
+
+    impl GameState for SumState {
+        type Action = usize;
+
+        fn new() -> Self {
+            Self {
+                total: 0,
+                turn: 0,
+            }
+        }
+
+        fn player(&self) -> usize {
+            self.turn % 2
+        }
+
+        fn rewards(&self) -> Option<Vec<f64>> {
+            if self.turn < 4 {
+                return None;
+            }
+            let score = self.total as f64 / 12.0;
+            Some(vec![score, 1.0 - score])
+        }
+
+        fn legal_actions(&self) -> Vec<usize> {
+            if self.turn < 4 { (0..4).collect() } else { vec![] }
+        }
+
+        fn pretty_print(&self) {
+            println!("total: {} turn: {}", self.total, self.turn);
+        }
+
+        fn next_state(&self, action: &usize) -> Self {
+            Self {
+                total: self.total + action,
+                turn: self.turn + 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_structured_actions() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(1000));
+        assert_eq!(agent.choose_action(&NimState::new()), Some(Take(1)));
+        let mut random = RandomAgent::new();
+        assert!(random.choose_action(&NimState::new()).is_some());
+    }
+
+    #[test]
+    fn test_search_iterations() {
+        let config = SearchConfig::new().iterations(10);
+        let mut agent = MonteCarloAgent::new().with_config(config);
+        let state = TicTacToeState::new();
+        let (action, reason) = agent.search(&state);
+        assert!(action.is_some());
+        assert_eq!(reason, StopReason::Iterations);
+    }
+
+    #[test]
+    fn test_search_max_nodes() {
+        let config = SearchConfig::new().iterations(1000).max_nodes(5);
+        let mut agent = MonteCarloAgent::new().with_config(config);
+        let state = TicTacToeState::new();
+        let (_, reason) = agent.search(&state);
+        assert_eq!(reason, StopReason::MaxNodes);
+    }
+
+    #[test]
+    fn test_search_time_limit() {
+        let config = SearchConfig::new()
+            .time_limit(std::time::Duration::from_millis(1));
+        let mut agent = MonteCarloAgent::new().with_config(config);
+        let state = TicTacToeState::new();
+        let (_, reason) = agent.search(&state);
+        assert_eq!(reason, StopReason::TimeLimit);
+        // Limits hit before the first iteration still give a move
+        let limits = [SearchConfig::new().iterations(0),
+                      SearchConfig::new().max_nodes(1),
+                      SearchConfig::new().time_limit(Duration::from_secs(0))];
+        for config in limits {
+            let mut agent = MonteCarloAgent::new().with_config(config);
+            let result = agent.analyze(&state);
+            assert_eq!(result.iterations, 0);
+            assert!(state.legal_actions().contains(&result.action.unwrap()));
+        }
+        // No limits at all fall back to the default time limit
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new());
+        let (_, reason) = agent.search(&state);
+        assert_eq!(reason, StopReason::TimeLimit);
+    }
+
+    #[test]
+    fn test_selection_policies() {
+        let policies: Vec<Box<dyn SelectionPolicy>> = vec![
+            Box::new(Ucb1::default()),
+            Box::new(Ucb1Tuned),
+            Box::new(UcbV::default()),
+            Box::new(Puct::default()),
+            Box::new(ThompsonSampling),
+        ];
+        let state = TicTacToeState::new();
+        for policy in policies {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(50));
+            agent.selection_policy = policy;
+            assert!(agent.choose_action(&state).is_some());
+        }
+    }
+
+    #[test]
+    fn test_simulate_cutoff() {
+        let agent = MonteCarloAgent::new()
+            .with_rollout_cutoff(2, |_: &TicTacToeState| vec![0.0, 0.25, 0.75]);
+        let state = TicTacToeState::new();
+        let rng = &mut rand::thread_rng();
+        assert_eq!(agent.simulate(state.clone(), rng).0,
+                   Some(vec![0.0, 0.25, 0.75]));
+        let mut state = state;
+        state.board = [0, 1, 2,
+                       2, 1, 1,
+                       1, 2, 2];
+        // Game ends before the cutoff
+        assert_eq!(agent.simulate(state, rng).0, Some(vec![0.0, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        for parallelism in [Parallelism::Off, Parallelism::Root(2),
+                            Parallelism::Tree(2)] {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(300))
+                .with_parallelism(parallelism)
+                .with_seed(4);
+            let result = agent.analyze(&state);
+            assert_eq!(result.action, Some(2));
+            assert_eq!(result.stop_reason, StopReason::Iterations);
+            let threads = match parallelism {
+                Parallelism::Root(threads) => threads,
+                _ => 1,
+            };
+            assert_eq!(result.iterations, 300 * threads);
+            assert!(result.nodes > 3 * threads);
+            assert!(result.max_depth > 1);
+            assert_eq!(result.principal_variation[0], 2);
+            let actions = result.actions.iter()
+                .map(|action| action.action)
+                .collect::<Vec<usize>>();
+            assert_eq!(actions, state.legal_actions());
+            let visits = result.actions.iter()
+                .map(|action| action.visits)
+                .sum::<f64>();
+            assert_eq!(visits, 300.0 * threads as f64);
+            for action in result.actions.iter() {
+                assert!(action.confidence.0 <= action.mean);
+                assert!(action.mean <= action.confidence.1);
+            }
+            let winning = &result.actions[0];
+            assert_eq!(winning.mean, 1.0);
+        }
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(300))
+            .with_transpositions(true)
+            .with_seed(4);
+        let result = agent.analyze(&state);
+        let actions = result.actions.iter()
+            .map(|action| action.action)
+            .collect::<Vec<usize>>();
+        assert_eq!(actions, state.legal_actions());
+        assert_eq!(result.actions[0].mean, 1.0);
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracing() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(10))
+            .with_seed(4);
+        tracing::subscriber::with_default(subscriber, || {
+            agent.choose_action(&TicTacToeState::new());
+        });
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone())
+            .unwrap();
+        assert!(out.contains("search done"), "{}", out);
+        assert!(out.contains("search{parallelism=Off}:iteration{i=0}: "));
+        assert!(out.contains(":expansion: "));
+        assert!(out.contains("`- id:0 action:root wins:"));
+    }
+
+    #[test]
+    fn test_final_move_policy() {
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        for policy in [Box::new(MaxValue) as Box<dyn FinalMovePolicy>,
+                       Box::new(MaxChild),
+                       Box::new(SecureChild::default()),
+                       Box::new(VisitSampling { temperature: 0.0 })] {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(1000))
+                .with_seed(2);
+            agent.final_move_policy = policy;
+            assert_eq!(agent.choose_action(&state), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_rollout_policies() {
+        let state = TicTacToeState::new();
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(50))
+            .with_rollout_policy(DecisiveMove { anti_decisive: true });
+        assert!(agent.choose_action(&state).is_some());
+    }
+
+    #[test]
+    fn test_back_prop() {
+        let agent = MonteCarloAgent::new();
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        let child_id = tree.add_node(0, 0);
+        let grandchild_id = tree.add_node(1, child_id);
+        let path = [0, child_id, grandchild_id];
+        agent.back_prop(&path, &tree, Some(&[0.0, 1.0, 0.0]), 1.0);
+        agent.back_prop(&path[..2], &tree, None, 1.0);
+        assert_eq!(tree.stats(0).plays, 2.0);
+        assert_eq!(tree.stats(child_id).wins, 1.0);
+        assert_eq!(tree.stats(child_id).plays, 2.0);
+        assert_eq!(tree.stats(grandchild_id).wins, 0.0);
+        assert_eq!(tree.stats(grandchild_id).plays, 1.0);
+        agent.back_prop(&path, &tree, Some(&[0.0, 0.5, 0.5]), 4.0);
+        assert_eq!(tree.stats(0).plays, 6.0);
+        assert_eq!(tree.stats(child_id).wins, 3.0);
+        assert_eq!(tree.stats(grandchild_id).sq_wins, 1.0);
+    }
+
+    #[test]
+    fn test_leaf_rollouts() {
+        let config = SearchConfig::new().iterations(100).leaf_rollouts(8);
+        let mut agent = MonteCarloAgent::new()
+            .with_config(config)
+            .with_tree_reuse(true)
+            .with_seed(5);
+        let state = NimState::new();
+        let rng = &mut rand::thread_rng();
+        let (rewards, weight, _) = agent.simulate_leaf(state.clone(), rng);
+        let rewards = rewards.unwrap();
+        assert_eq!(weight, 8.0);
+        assert!((rewards[0] + rewards[1] - 1.0).abs() < 1e-9);
+        assert_eq!(agent.choose_action(&state), Some(Take(1)));
+        // Terminal nodes are not simulated and count once
+        let plays = agent.tree.as_ref().unwrap().stats(0).plays;
+        assert!(plays > 100.0 && plays <= 800.0);
+    }
+
+    #[test]
+    fn test_winning_move() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(500));
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        assert_eq!(agent.choose_action(&state), Some(2));
+        state.player = 2;
+        assert_eq!(agent.choose_action(&state), Some(8));
+    }
+
+    #[test]
+    fn test_selection_state() {
+        let agent = MonteCarloAgent::new();
+        let rng = &mut rand::thread_rng();
+        let mut tree = NodeTree::new(&NimState::new());
+        tree.state_cache = StateCache::Every(2);
+        tree.stats_mut(0).plays = 3.0;
+        for take in 1..4 {
+            let child_id = tree.add_node(Take(take), 0);
+            tree.stats_mut(child_id).plays = 1.0;
+        }
+        tree.stats_mut(2).wins = 1.0;
+        let (path, state) = agent.selection(0, &tree, &tree.root_state, rng);
+        assert_eq!(path, vec![0, 2]);
+        assert_eq!(state.stones, 3);
+        // Descends through fully expanded nodes, past cached states
+        for take in 1..4 {
+            tree.add_node(Take(take), 2);
+        }
+        let (path, state) = agent.selection(0, &tree, &tree.root_state, rng);
+        let node_id = path[2];
+        assert_eq!(tree.get(node_id).parent, Some(2));
+        assert!(tree.cached_state(node_id).is_some());
+        assert_eq!(state.stones, tree.state(node_id).stones);
+    }
+
+    #[test]
+    fn test_selection_state_transpositions() {
+        let rng = &mut rand::thread_rng();
+        for k in 1..3 {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(500))
+                .with_transpositions(true)
+                .with_state_cache(StateCache::Every(k))
+                .with_tree_reuse(true)
+                .with_seed(k as u64);
+            let mut state = TicTacToeState::new();
+            for _ in 0..3 {
+                let action = agent.choose_action(&state).unwrap();
+                // Cached states of nodes reached through links are in
+                // another orientation than the path to them
+                let tree = agent.tree().unwrap();
+                for _ in 0..200 {
+                    let (path, selected) =
+                        agent.selection(0, tree, &tree.root_state, rng);
+                    let mut replayed = tree.root_state.clone();
+                    for &node_id in &path[1..] {
+                        let action = tree.get(node_id).action.as_ref();
+                        replayed = tree.next_state(&replayed,
+                                                   action.unwrap());
+                    }
+                    assert_eq!(selected.board, replayed.board);
+                }
+                agent.observe(&state, &action);
+                state = state.next_state(&action);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_reuse() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(200))
+            .with_tree_reuse(true);
+        let state = TicTacToeState::new();
+        let action = agent.choose_action(&state).unwrap();
+        agent.observe(&state, &action);
+        let state = state.next_state(&action);
+
+        let tree = agent.tree.as_ref().unwrap();
+        let node_id = tree.find_child(0, &action).unwrap();
+        let reply_id = tree.children(node_id).next().unwrap();
+        let reply = tree.get(reply_id).action.unwrap();
+        let plays = tree.stats(reply_id).plays;
+        agent.observe(&state, &reply);
+        let state = state.next_state(&reply);
+
+        let tree = agent.reused_tree(&state).unwrap();
+        assert_eq!(tree.stats(0).plays, plays);
+        assert_eq!(tree.root_state.board, state.board);
+        for node_id in 1..tree.len() {
+            assert!(tree.get(node_id).parent.unwrap() < node_id);
+        }
+
+        // A move the tree never explored starts over
+        agent.tree = Some(tree);
+        agent.observe(&state, &9);
+        assert!(agent.reused_tree(&state).is_none());
+    }
+
+    #[test]
+    fn test_tree_reuse_unobserved() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(200))
+            .with_tree_reuse(true);
+        agent.choose_action(&TicTacToeState::new());
+        // The moves leading here were never observed
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 2, 0,
+                       2, 0, 0];
+        let action = agent.choose_action(&state).unwrap();
+        assert!(state.legal_actions().contains(&action));
+        let tree = agent.tree().unwrap();
+        let actions = tree.children(0)
+            .map(|child_id| tree.get(child_id).action.unwrap())
+            .collect::<Vec<usize>>();
+        assert_eq!(actions, state.legal_actions());
+    }
+
+    #[test]
+    fn test_tree_reuse_without_hash() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(100))
+            .with_tree_reuse(true);
+        let state = NimState::new();
+        let action = agent.choose_action(&state).unwrap();
+        agent.observe(&state, &action);
+        // Nothing to tell the position reached from another one
+        assert!(agent.reused_tree(&state.next_state(&action)).is_none());
+    }
+
+    #[test]
+    fn test_seed() {
+        let search = |seed| {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(300))
+                .with_selection_policy(ThompsonSampling)
+                .with_tree_reuse(true)
+                .with_seed(seed);
+            let action = agent.choose_action(&TicTacToeState::new());
+            (action, agent.tree.unwrap())
+        };
+        let (action, tree) = search(7);
+        let (same_action, same_tree) = search(7);
+        assert_eq!(action, same_action);
+        assert_eq!(tree.len(), same_tree.len());
+        for node_id in 0..tree.len() {
+            assert_eq!(tree.get(node_id).action,
+                       same_tree.get(node_id).action);
+            assert_eq!(tree.stats(node_id), same_tree.stats(node_id));
+        }
+        let trees = (1..4).map(|seed| search(seed).1)
+            .collect::<Vec<NodeTree<TicTacToeState>>>();
+        assert!(trees.iter().any(|other| {
+            (0..tree.len().min(other.len())).any(|node_id| {
+                tree.stats(node_id) != other.stats(node_id)
+            })
+        }));
+    }
+
+    #[test]
+    fn test_root_parallelism() {
+        let search = || {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(500))
+                .with_parallelism(Parallelism::Root(4))
+                .with_seed(11);
+            let mut state = TicTacToeState::new();
+            state.board = [1, 1, 0,
+                           0, 0, 0,
+                           2, 2, 0];
+            agent.choose_action(&state)
+        };
+        assert_eq!(search(), Some(2));
+        assert_eq!(search(), search());
+    }
+
+    #[test]
+    fn test_tree_parallelism() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(400))
+            .with_parallelism(Parallelism::Tree(4))
+            .with_tree_reuse(true);
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        assert_eq!(agent.choose_action(&state), Some(2));
+        // Every iteration is counted once and no virtual loss is left over
+        let tree = agent.tree.as_ref().unwrap();
+        assert_eq!(tree.stats(0).plays, 400.0);
+        let plays = tree.children(0)
+            .map(|child_id| tree.stats(child_id).plays)
+            .sum::<f64>();
+        assert_eq!(plays, 400.0);
+    }
+
+    #[test]
+    fn test_transpositions() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(2000))
+            .with_transpositions(true)
+            .with_solver(true)
+            .with_tree_reuse(true)
+            .with_seed(3);
+        let mut state = TicTacToeState::new();
+        state.board = [1, 0, 0,
+                       0, 2, 0,
+                       0, 0, 0];
+        assert!(agent.choose_action(&state).is_some());
+        let tree = agent.tree.as_ref().unwrap();
+        let links = (0..tree.len())
+            .filter(|&node_id| tree.get(node_id).link.is_some())
+            .collect::<Vec<NodeId>>();
+        assert!(!links.is_empty());
+        // Positions are credited once per visit over all edges into them
+        for &node_id in links.iter() {
+            let position = tree.position(node_id);
+            assert!(tree.position_stats(node_id).plays
+                    >= tree.stats(node_id).plays
+                    + tree.stats(position).plays);
+        }
+        // Actions are played in the orientation of the state searched and
+        // the tree follows them through symmetric positions
+        let action = agent.choose_action(&state).unwrap();
+        assert_eq!(state.board[action], 0);
+        agent.observe(&state, &action);
+        let state = state.next_state(&action);
+        let tree = agent.reused_tree(&state).unwrap();
+        assert_eq!(tree.root_state.board, state.board);
+        assert_eq!(tree.get(0).link, None);
+        assert!(tree.has_children(0));
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        agent.reset();
+        assert_eq!(agent.choose_action(&state), Some(2));
+    }
+
+    #[test]
+    fn test_solver() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(1_000_000))
+            .with_solver(true)
+            .with_tree_reuse(true)
+            .with_seed(1);
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        assert_eq!(agent.search(&state), (Some(2), StopReason::Solved));
+        agent.reset();
+        state.player = 2;
+        assert_eq!(agent.search(&state), (Some(8), StopReason::Solved));
+        agent.reset();
+        // Player 2 loses whatever they do
+        state.board = [1, 1, 0,
+                       1, 2, 0,
+                       0, 0, 2];
+        let (action, reason) = agent.search(&state);
+        assert_eq!(reason, StopReason::Solved);
+        assert!(action.is_some());
+        let tree = agent.tree.as_ref().unwrap();
+        assert_eq!(tree.stats(0).proof, Proof::Win(1));
+    }
+
+    #[test]
+    fn test_reuse_solver_transpositions() {
+        for seed in 0..10 {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(1000))
+                .with_solver(true)
+                .with_transpositions(true)
+                .with_tree_reuse(true)
+                .with_seed(seed);
+            let mut state = TicTacToeState::new();
+            while state.rewards().is_none() {
+                let action = agent.choose_action(&state).unwrap();
+                // Proofs stay with the positions they were found for
+                let tree = agent.tree().unwrap();
+                for node_id in 0..tree.len() {
+                    if tree.get(node_id).link.is_none()
+                        && tree.has_children(node_id) {
+                        let proof = tree.stats(node_id).proof;
+                        assert!(proof == Proof::Unknown || proof
+                                == MonteCarloAgent::children_proof(node_id,
+                                                                   tree));
+                    }
+                }
+                agent.observe(&state, &action);
+                state = state.next_state(&action);
+            }
+        }
+    }
+
+    #[test]
+    fn test_best_action_score_bounds() {
+        let agent = MonteCarloAgent::new().with_score_bounds(true);
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        tree.add_node(0, 0);
+        tree.add_node(4, 0);
+        // The most visited child is refuted
+        for (node_id, plays, bounds) in [(0, 12.0, (0.5, 1.0)),
+                                         (1, 10.0, (0.0, 0.0)),
+                                         (2, 2.0, (0.5, 0.5))] {
+            let stats = tree.stats_mut(node_id);
+            stats.plays = plays;
+            stats.pessimistic = bounds.0;
+            stats.optimistic = bounds.1;
+        }
+        let rng = &mut rand::thread_rng();
+        assert_eq!(agent.best_action(0, &tree, rng), Some(4));
+        // Solved, so only the child holding the value will do
+        tree.stats_mut(0).optimistic = 0.5;
+        tree.stats_mut(1).optimistic = 1.0;
+        assert_eq!(agent.best_action(0, &tree, rng), Some(4));
+
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        for seed in 0..10 {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new().iterations(1000))
+                .with_score_bounds(true)
+                .with_seed(seed);
+            assert_eq!(agent.choose_action(&state), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_best_action_unproven_children() {
+        let agent = MonteCarloAgent::new().with_solver(true);
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        tree.add_node(0, 0);
+        tree.add_node(4, 0);
+        tree.stats_mut(1).plays = 1.0;
+        // Proven a draw without a child proven to hold it
+        tree.stats_mut(0).proof = Proof::Draw;
+        let rng = &mut rand::thread_rng();
+        assert_eq!(agent.best_action(0, &tree, rng), Some(0));
+        // Every child lost
+        tree.stats_mut(1).proof = Proof::Win(2);
+        tree.stats_mut(2).proof = Proof::Win(2);
+        assert_eq!(agent.best_action(0, &tree, rng), Some(0));
+    }
+
+    #[test]
+    fn test_solver_draw() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(200_000))
+            .with_solver(true)
+            .with_tree_reuse(true)
+            .with_seed(1);
+        let state = TicTacToeState::new();
+        let (action, reason) = agent.search(&state);
+        assert_eq!(reason, StopReason::Solved);
+        assert!(action.is_some());
+        let tree = agent.tree.as_ref().unwrap();
+        assert_eq!(tree.stats(0).proof, Proof::Draw);
+        // Any first move draws with best play
+        for child_id in tree.children(0) {
+            assert_eq!(tree.stats(child_id).proof, Proof::Draw);
+        }
+    }
+
+    #[test]
+    fn test_score_bounds() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(100_000))
+            .with_score_bounds(true)
+            .with_tree_reuse(true)
+            .with_seed(2);
+        let state = SumState::new();
+        assert_eq!(agent.search(&state), (Some(3), StopReason::Solved));
+        let tree = agent.tree.as_ref().unwrap();
+        assert_eq!(tree.stats(0).pessimistic, 0.5);
+        assert_eq!(tree.stats(0).optimistic, 0.5);
+        // Player 1 minimizes player 0's score
+        let reply_id = tree.children(tree.find_child(0, &3).unwrap())
+            .find(|&child_id| tree.get(child_id).action == Some(0))
+            .unwrap();
+        assert_eq!(tree.stats(reply_id).optimistic, 0.5);
+        let parent = NodeStats {
+            pessimistic: 0.25,
+            optimistic: 0.5,
+            ..NodeStats::new()
+        };
+        let child = NodeStats {
+            pessimistic: 0.0,
+            optimistic: 0.2,
+            ..NodeStats::new()
+        };
+        assert!(MonteCarloAgent::<SumState>::is_pruned(&parent, &child, true));
+        assert!(!MonteCarloAgent::<SumState>::is_pruned(&parent, &child,
+                                                         false));
+    }
+
+    #[test]
+    fn test_back_prop_amaf() {
+        let agent = MonteCarloAgent::new();
+        let mut tree = NodeTree::new(&NimState::new());
+        tree.expand(0, &NimState::new()); // 1, 2, 3 for Take(1) to Take(3)
+        let state = tree.state(1);
+        tree.expand(1, &state); // 4, 5, 6
+        let moves = vec![(0, Take(2)), (1, Take(1))];
+        agent.back_prop_amaf(&[0, 1, 5], &tree, &[0.0, 1.0], 2.0, &moves);
+        let amaf_plays = (0..tree.len())
+            .map(|node_id| tree.stats(node_id).amaf_plays)
+            .collect::<Vec<f64>>();
+        assert_eq!(amaf_plays, vec![0.0, 2.0, 2.0, 0.0, 2.0, 2.0, 0.0]);
+        assert_eq!(tree.stats(1).amaf_wins, 0.0);
+        assert_eq!(tree.stats(5).amaf_wins, 2.0);
+    }
+
+    #[test]
+    fn test_back_prop_amaf_symmetries() {
+        let agent = MonteCarloAgent::new();
+        let root_state = TicTacToeState::new();
+        let mut tree = NodeTree::new(&root_state);
+        tree.transpositions = true;
+        tree.expand(0, &root_state);
+        let node_id = tree.find_child(0, &0).unwrap();
+        let state = tree.state(node_id);
+        tree.expand(node_id, &state);
+        let reply = tree.tree_action(&state, &1).unwrap();
+        let reply_id = tree.find_child(node_id, &reply).unwrap();
+        // Rollout moves are as played in the game
+        let moves = vec![(1, 2), (2, 5)];
+        agent.back_prop_amaf(&[0, node_id, reply_id], &tree, &[0.0, 1.0, 0.0],
+                             1.0, &moves);
+        let credited = |node_id, state: &TicTacToeState| {
+            let mut actions = tree.children(node_id)
+                .filter(|&child_id| tree.stats(child_id).amaf_plays > 0.0)
+                .map(|child_id| {
+                    tree.game_action(state,
+                                     tree.get(child_id).action.as_ref()
+                                         .unwrap())
+                })
+                .collect::<Vec<usize>>();
+            actions.sort();
+            actions
+        };
+        assert_eq!(credited(0, &root_state), vec![0, 2]);
+        assert_eq!(credited(node_id, &state), vec![1, 5]);
+    }
+
+    #[test]
+    fn test_rave() {
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(500))
+            .with_rave(RaveSchedule::default())
+            .with_tree_reuse(true);
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        assert_eq!(agent.choose_action(&state), Some(2));
+        let tree = agent.tree.as_ref().unwrap();
+        for child_id in tree.children(0) {
+            let stats = tree.stats(child_id);
+            assert!(stats.amaf_plays >= stats.plays);
+        }
+    }
+
+    #[test]
+    fn test_merge_roots() {
+        let state = NimState::new();
+        let mut trees = Vec::new();
+        for wins in 1..3 {
+            let mut tree = NodeTree::new(&state);
+            // Children in a different order in each tree
+            for take in (1..4).rev().take(wins + 1) {
+                let child_id = tree.add_node(Take(take), 0);
+                tree.stats_mut(child_id).wins = wins as f64;
+                tree.stats_mut(child_id).plays = 2.0;
+            }
+            tree.stats_mut(0).plays = 10.0;
+            trees.push(tree);
+        }
+        let trees = trees.iter().collect::<Vec<&NodeTree<NimState>>>();
+        let merged = MonteCarloAgent::merge_roots(&state, &trees);
+        assert_eq!(merged.stats(0).plays, 20.0);
+        let take = |n| merged.stats(merged.find_child(0, &Take(n)).unwrap());
+        assert_eq!(take(3).wins, 3.0);
+        assert_eq!(take(3).plays, 4.0);
+        assert_eq!(take(1).wins, 2.0);
+        assert_eq!(take(1).plays, 2.0);
+    }
+
+    // cargo test --release bench -- --ignored --nocapture > /dev/null
+    #[test]
+    #[ignore]
+    fn bench_iterations_per_second() {
+        let iterations = 20000;
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(iterations));
+        let now = std::time::Instant::now();
+        agent.choose_action(&TicTacToeState::new());
+        let elapsed = now.elapsed().as_secs_f64();
+        eprintln!("{:.0} iterations/sec", iterations as f64 / elapsed);
+    }
+
+    // Scaling of tree parallelism, meaningful on a machine with at least 8
+    // cores. Run like bench_iterations_per_second.
+    #[test]
+    #[ignore]
+    fn bench_tree_parallelism() {
+        let mut base = 0.0;
+        for threads in [1, 2, 4, 8] {
+            let mut agent = MonteCarloAgent::new()
+                .with_config(SearchConfig::new()
+                             .time_limit(Duration::from_secs(2)))
+                .with_parallelism(Parallelism::Tree(threads))
+                .with_seed(1);
+            let result = agent.analyze(&TicTacToeState::new());
+            let rate = result.iterations as f64
+                / result.elapsed.as_secs_f64();
+            if threads == 1 {
+                base = rate;
+            }
+            eprintln!("Tree({}): {:.0} iterations/sec, {:.2}x", threads,
+                      rate, rate / base);
+        }
+    }
+
 //     #[test]
 //     fn test() {
 //         let agent = MonteCarloAgent {};
@@ -1,17 +1,62 @@
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{RngCore, SeedableRng};
+use crate::agent::*;
 use crate::game::*;
 
-#[derive(Debug, Clone)]
 pub struct RandomAgent {
+    rng: Box<dyn RngCore + Send>,
 }
 
 impl RandomAgent {
 
-    pub fn choose_action(&self, state: &impl GameState) -> Option<usize> {
+    pub fn new() -> Self {
+        Self {
+            rng: Box::new(StdRng::from_entropy()),
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    pub fn with_rng(mut self, rng: impl RngCore + Send + 'static) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+
+    pub fn choose_action<S: GameState>(&mut self, state: &S
+                                       ) -> Option<S::Action> {
         let actions = state.legal_actions();
-        let chosen = actions.choose(&mut rand::thread_rng());
-//         let chosen = actions.choose(&mut StdRng::from_entropy());
-        return chosen.copied();
+        let chosen = actions.choose(&mut self.rng);
+        return chosen.cloned();
+    }
+
+}
+
+impl<S: GameState> Agent<S> for RandomAgent {
+    fn choose_action(&mut self, state: &S) -> Option<S::Action> {
+        RandomAgent::choose_action(self, state)
+    }
+
+    fn name(&self) -> String {
+        "RandomAgent".to_string()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tic_tac_toe::*;
 
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let state = TicTacToeState::new();
+        let mut agent = RandomAgent::new().with_seed(3);
+        let mut same = RandomAgent::new().with_seed(3);
+        for _ in 0..10 {
+            assert_eq!(agent.choose_action(&state), same.choose_action(&state));
+        }
+    }
 }
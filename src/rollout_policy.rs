@@ -0,0 +1,211 @@
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use crate::game::*;
+
+// Picks the moves played during simulation. Returning None ends the
+// rollout.
+pub trait RolloutPolicy<S: GameState>: Send + Sync {
+    fn choose_action(&self, state: &S, rng: &mut dyn RngCore
+                     ) -> Option<S::Action>;
+}
+
+pub type ActionHeuristic<S> =
+    Box<dyn Fn(&S, &<S as GameState>::Action) -> f64 + Send + Sync>;
+
+#[derive(Debug, Clone, Default)]
+pub struct UniformRandom;
+
+impl<S: GameState> RolloutPolicy<S> for UniformRandom {
+    fn choose_action(&self, state: &S, mut rng: &mut dyn RngCore
+                     ) -> Option<S::Action> {
+        state.legal_actions().choose(&mut rng).cloned()
+    }
+}
+
+// Plays the action the heuristic scores highest, or a random one with
+// probability epsilon.
+pub struct EpsilonGreedy<S: GameState> {
+    pub epsilon: f64,
+    pub heuristic: ActionHeuristic<S>,
+}
+
+impl<S: GameState> EpsilonGreedy<S> {
+    pub fn new(epsilon: f64,
+               heuristic: impl Fn(&S, &S::Action) -> f64 + Send + Sync
+                   + 'static
+               ) -> Self {
+        Self {
+            epsilon: epsilon,
+            heuristic: Box::new(heuristic),
+        }
+    }
+}
+
+impl<S: GameState> RolloutPolicy<S> for EpsilonGreedy<S> {
+    fn choose_action(&self, state: &S, mut rng: &mut dyn RngCore
+                     ) -> Option<S::Action> {
+        let actions = state.legal_actions();
+        if rng.gen::<f64>() < self.epsilon {
+            return actions.choose(&mut rng).cloned();
+        }
+        let mut best = (None, f64::NEG_INFINITY); // (action, value)
+        for action in actions {
+            let value = (self.heuristic)(state, &action);
+            if best.0.is_none() || value > best.1 {
+                best = (Some(action), value);
+            }
+        }
+        best.0
+    }
+}
+
+// Samples actions with probability proportional to exp(weight / temperature).
+pub struct Softmax<S: GameState> {
+    pub temperature: f64,
+    pub weight: ActionHeuristic<S>,
+}
+
+impl<S: GameState> Softmax<S> {
+    pub fn new(temperature: f64,
+               weight: impl Fn(&S, &S::Action) -> f64 + Send + Sync
+                   + 'static
+               ) -> Self {
+        Self {
+            temperature: temperature,
+            weight: Box::new(weight),
+        }
+    }
+}
+
+impl<S: GameState> RolloutPolicy<S> for Softmax<S> {
+    fn choose_action(&self, state: &S, mut rng: &mut dyn RngCore
+                     ) -> Option<S::Action> {
+        let actions = state.legal_actions();
+        let weights = actions.iter()
+            .map(|action| (self.weight)(state, action) / self.temperature)
+            .collect::<Vec<f64>>();
+        // Shift by the max so exp doesn't overflow
+        let max = weights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let choices = actions.into_iter().zip(weights.iter())
+            .map(|(action, weight)| (action, f64::exp(weight - max)))
+            .collect::<Vec<(S::Action, f64)>>();
+        choices.choose_weighted(&mut rng, |choice| choice.1).ok()
+            .map(|choice| choice.0.clone())
+    }
+}
+
+// Plays a winning move when there is one. With anti_decisive set it also
+// avoids moves that hand the opponent a winning reply. Otherwise plays
+// uniformly at random.
+#[derive(Debug, Clone, Default)]
+pub struct DecisiveMove {
+    pub anti_decisive: bool,
+}
+
+impl DecisiveMove {
+    // A move is decisive if it ends the game with the mover strictly ahead
+    // of every other player.
+    fn is_decisive<S: GameState>(state: &S, action: &S::Action) -> bool {
+        let mover = state.player();
+        match state.next_state(action).rewards() {
+            Some(rewards) => rewards.iter().enumerate()
+                .all(|(player, &reward)| {
+                    player == mover || reward < rewards[mover]
+                }),
+            None => false,
+        }
+    }
+
+    fn decisive_action<S: GameState>(state: &S) -> Option<S::Action> {
+        state.legal_actions().into_iter()
+            .find(|action| Self::is_decisive(state, action))
+    }
+}
+
+impl<S: GameState> RolloutPolicy<S> for DecisiveMove {
+    fn choose_action(&self, state: &S, mut rng: &mut dyn RngCore
+                     ) -> Option<S::Action> {
+        if let Some(action) = Self::decisive_action(state) {
+            return Some(action);
+        }
+        let actions = state.legal_actions();
+        if self.anti_decisive {
+            let safe = actions.iter().filter(|action| {
+                let next = state.next_state(action);
+                next.reward().is_some()
+                    || Self::decisive_action(&next).is_none()
+            }).collect::<Vec<&S::Action>>();
+            if !safe.is_empty() {
+                return safe.choose(&mut rng).map(|&action| action.clone());
+            }
+        }
+        actions.choose(&mut rng).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    #[test]
+    fn test_epsilon_greedy() {
+        let state = TicTacToeState::new();
+        let policy = EpsilonGreedy::new(0.0, |_: &TicTacToeState, &action| {
+            if action == 4 { 1.0 } else { 0.0 }
+        });
+        let rng = &mut rand::thread_rng();
+        assert_eq!(policy.choose_action(&state, rng), Some(4));
+    }
+
+    #[test]
+    fn test_softmax() {
+        let state = TicTacToeState::new();
+        let policy = Softmax::new(0.01, |_: &TicTacToeState, &action| {
+            if action == 8 { 1.0 } else { 0.0 }
+        });
+        let rng = &mut rand::thread_rng();
+        assert_eq!(policy.choose_action(&state, rng), Some(8));
+    }
+
+    #[test]
+    fn test_decisive() {
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 2, 0,
+                       2, 0, 0];
+        let policy = DecisiveMove { anti_decisive: false };
+        let rng = &mut rand::thread_rng();
+        assert_eq!(policy.choose_action(&state, rng), Some(2));
+    }
+
+    #[test]
+    fn test_anti_decisive() {
+        let mut state = TicTacToeState::new();
+        state.board = [2, 2, 0,
+                       0, 1, 0,
+                       1, 0, 0];
+        let policy = DecisiveMove { anti_decisive: true };
+        let rng = &mut rand::thread_rng();
+        // 2 is also a win for player 1
+        assert_eq!(policy.choose_action(&state, rng), Some(2));
+        state.board = [2, 2, 0,
+                       0, 0, 0,
+                       1, 0, 0];
+        for _ in 0..10 {
+            assert_eq!(policy.choose_action(&state, rng), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_uniform_random() {
+        let mut state = TicTacToeState::new();
+        state.board = [0, 2, 2,
+                       1, 1, 2,
+                       2, 1, 1];
+        let rng = &mut rand::thread_rng();
+        assert_eq!(UniformRandom.choose_action(&state, rng), Some(0));
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+// The time limit of configs without any limits, see SearchConfig::new
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    TimeLimit,
+    Iterations,
+    MaxNodes,
+    // The solver proved the value of the root
+    Solved,
+}
+
+// Limits for a single search. Any combination may be set and the search
+// stops on whichever is hit first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchConfig {
+    pub time_limit: Option<Duration>,
+    pub iterations: Option<usize>,
+    // Checked between iterations, and an iteration expands all children of
+    // a node at once, so the tree may end up larger by up to one node's
+    // number of legal actions.
+    pub max_nodes: Option<usize>,
+    // Rollouts run in parallel from every simulated leaf. Their rewards are
+    // averaged and backed up as this many plays.
+    pub leaf_rollouts: usize,
+}
+
+impl SearchConfig {
+
+    // A config with no limits set. Searching with it stops after
+    // DEFAULT_TIME_LIMIT unless limits are added.
+    pub fn new() -> Self {
+        Self {
+            time_limit: None,
+            iterations: None,
+            max_nodes: None,
+            leaf_rollouts: 1,
+        }
+    }
+
+    pub fn time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub fn leaf_rollouts(mut self, leaf_rollouts: usize) -> Self {
+        self.leaf_rollouts = leaf_rollouts;
+        self
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.time_limit.is_some() || self.iterations.is_some()
+            || self.max_nodes.is_some()
+    }
+
+    pub fn stop_reason(&self, elapsed: Duration, iterations: usize,
+                       nodes: usize) -> Option<StopReason> {
+        let time_limit = if self.is_bounded() {
+            self.time_limit
+        } else {
+            Some(DEFAULT_TIME_LIMIT)
+        };
+        if let Some(limit) = self.iterations {
+            if iterations >= limit {
+                return Some(StopReason::Iterations);
+            }
+        }
+        if let Some(limit) = self.max_nodes {
+            if nodes >= limit {
+                return Some(StopReason::MaxNodes);
+            }
+        }
+        if let Some(limit) = time_limit {
+            if elapsed >= limit {
+                return Some(StopReason::TimeLimit);
+            }
+        }
+        None
+    }
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self::new().time_limit(DEFAULT_TIME_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_reason() {
+        let config = SearchConfig::new()
+            .time_limit(Duration::from_millis(10))
+            .iterations(5)
+            .max_nodes(20);
+        let zero = Duration::from_millis(0);
+        assert_eq!(config.stop_reason(zero, 0, 1), None);
+        assert_eq!(config.stop_reason(zero, 5, 1),
+                   Some(StopReason::Iterations));
+        assert_eq!(config.stop_reason(zero, 4, 20),
+                   Some(StopReason::MaxNodes));
+        assert_eq!(config.stop_reason(Duration::from_millis(10), 4, 19),
+                   Some(StopReason::TimeLimit));
+    }
+
+    #[test]
+    fn test_is_bounded() {
+        assert_eq!(SearchConfig::new().is_bounded(), false);
+        assert_eq!(SearchConfig::default().is_bounded(), true);
+        assert_eq!(SearchConfig::new().max_nodes(1).is_bounded(), true);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let config = SearchConfig::new();
+        assert_eq!(config.stop_reason(Duration::from_millis(499), 1000, 1000),
+                   None);
+        assert_eq!(config.stop_reason(DEFAULT_TIME_LIMIT, 0, 1),
+                   Some(StopReason::TimeLimit));
+    }
+}
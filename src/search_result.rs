@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use crate::game::*;
+use crate::search_config::*;
+use crate::tree_node::*;
+
+// How the search rated one action at the root. Values are rewards of the
+// player to move at the root.
+#[derive(Debug, Clone)]
+pub struct ActionStats<S: GameState> {
+    pub action: S::Action,
+    pub visits: f64,
+    // NaN if the action was never visited
+    pub mean: f64,
+    // 95% confidence interval of the mean from the normal approximation.
+    // The whole reward range while there are fewer than two visits.
+    pub confidence: (f64, f64),
+}
+
+impl<S: GameState> ActionStats<S> {
+    pub fn new(action: S::Action, stats: &NodeStats, bounds: (f64, f64)
+               ) -> Self {
+        let mean = stats.wins / stats.plays;
+        let confidence = if stats.plays < 2.0 {
+            bounds
+        } else {
+            let variance = (stats.sq_wins / stats.plays - mean * mean)
+                .max(0.0);
+            let margin = 1.96 * f64::sqrt(variance / stats.plays);
+            (mean - margin, mean + margin)
+        };
+        Self {
+            action: action,
+            visits: stats.plays,
+            mean: mean,
+            confidence: confidence,
+        }
+    }
+}
+
+// Everything a search found out, see MonteCarloAgent::analyze.
+#[derive(Debug, Clone)]
+pub struct SearchResult<S: GameState> {
+    // The action chosen, None if the root has no legal actions
+    pub action: Option<S::Action>,
+    // Every root action in the order of GameState::legal_actions
+    pub actions: Vec<ActionStats<S>>,
+    // The line the search expects, following the most visited children
+    pub principal_variation: Vec<S::Action>,
+    pub iterations: usize,
+    // Nodes in the tree, over all trees with root parallelism
+    pub nodes: usize,
+    pub max_depth: usize,
+    pub elapsed: Duration,
+    pub stop_reason: StopReason,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    #[test]
+    fn test_action_stats() {
+        let mut stats = NodeStats::new();
+        stats.wins = 1.0;
+        stats.sq_wins = 1.0;
+        stats.plays = 1.0;
+        let action = ActionStats::<TicTacToeState>::new(4, &stats,
+                                                         (0.0, 1.0));
+        assert_eq!(action.mean, 1.0);
+        assert_eq!(action.confidence, (0.0, 1.0));
+        stats.plays = 4.0;
+        let action = ActionStats::<TicTacToeState>::new(4, &stats,
+                                                         (0.0, 1.0));
+        assert_eq!(action.mean, 0.25);
+        let margin = 1.96 * f64::sqrt(0.1875 / 4.0);
+        assert_eq!(action.confidence, (0.25 - margin, 0.25 + margin));
+    }
+}
@@ -0,0 +1,269 @@
+use rand::RngCore;
+use rand_distr::{Beta, Distribution};
+
+use crate::tree_node::*;
+
+// Scores a child during selection, the child with the highest score is
+// descended into. Unvisited children score infinity unless the policy has
+// a better idea (e.g. priors).
+pub trait SelectionPolicy: Send + Sync {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             rng: &mut dyn RngCore) -> f64;
+}
+
+fn mean(node: &NodeStats) -> f64 {
+    node.wins / node.plays
+}
+
+fn variance(node: &NodeStats) -> f64 {
+    let mean = mean(node);
+    (node.sq_wins / node.plays - mean * mean).max(0.0)
+}
+
+// mean + c * sqrt(ln N / n)
+#[derive(Debug, Clone)]
+pub struct Ucb1 {
+    pub c: f64,
+}
+
+impl Default for Ucb1 {
+    fn default() -> Self {
+        Self {
+            c: std::f64::consts::SQRT_2,
+        }
+    }
+}
+
+impl SelectionPolicy for Ucb1 {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             _: &mut dyn RngCore) -> f64 {
+        if child.plays == 0.0 {
+            return f64::INFINITY;
+        }
+        mean(child) + self.c * f64::sqrt(f64::ln(parent.plays) / child.plays)
+    }
+}
+
+// UCB1 with the exploration term bounded by the reward variance, from Auer
+// et al. Assumes rewards in [0, 1].
+#[derive(Debug, Clone, Default)]
+pub struct Ucb1Tuned;
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             _: &mut dyn RngCore) -> f64 {
+        if child.plays == 0.0 {
+            return f64::INFINITY;
+        }
+        let log_ratio = f64::ln(parent.plays) / child.plays;
+        let bound = variance(child) + f64::sqrt(2.0 * log_ratio);
+        mean(child) + f64::sqrt(log_ratio * bound.min(0.25))
+    }
+}
+
+// UCB-V from Audibert et al. `b` is the width of the reward range and `c`
+// weights the range term.
+#[derive(Debug, Clone)]
+pub struct UcbV {
+    pub c: f64,
+    pub b: f64,
+}
+
+impl Default for UcbV {
+    fn default() -> Self {
+        Self {
+            c: 1.0,
+            b: 1.0,
+        }
+    }
+}
+
+impl SelectionPolicy for UcbV {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             _: &mut dyn RngCore) -> f64 {
+        if child.plays == 0.0 {
+            return f64::INFINITY;
+        }
+        let log_ratio = f64::ln(parent.plays) / child.plays;
+        mean(child) + f64::sqrt(2.0 * variance(child) * log_ratio)
+            + self.c * 3.0 * self.b * log_ratio
+    }
+}
+
+// mean + c * prior * sqrt(N) / (1 + n), as used by AlphaZero. Priors come
+// from GameState::prior when the child is added. Unvisited children have a
+// mean of zero.
+#[derive(Debug, Clone)]
+pub struct Puct {
+    pub c: f64,
+}
+
+impl Default for Puct {
+    fn default() -> Self {
+        Self {
+            c: 1.5,
+        }
+    }
+}
+
+impl SelectionPolicy for Puct {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             _: &mut dyn RngCore) -> f64 {
+        let mean = if child.plays == 0.0 { 0.0 } else { mean(child) };
+        mean + self.c * child.prior * f64::sqrt(parent.plays)
+            / (1.0 + child.plays)
+    }
+}
+
+// Samples from a Beta(wins + 1, losses + 1) posterior, treating rewards in
+// [0, 1] as fractional wins.
+#[derive(Debug, Clone, Default)]
+pub struct ThompsonSampling;
+
+impl SelectionPolicy for ThompsonSampling {
+    fn score(&self, _: &NodeStats, child: &NodeStats,
+             rng: &mut dyn RngCore) -> f64 {
+        let alpha = child.wins.max(0.0) + 1.0;
+        let beta = (child.plays - child.wins).max(0.0) + 1.0;
+        Beta::new(alpha, beta).unwrap().sample(rng)
+    }
+}
+
+// How much weight Rave gives the AMAF value over a child's own mean, falling
+// from 1 towards 0 as the child gets played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaveSchedule {
+    // sqrt(k / (3n + k)), both values get equal weight after k plays
+    HandSelected { k: f64 },
+    // The minimum MSE schedule from Gelly and Silver. `bias` is the expected
+    // difference between AMAF and actual values.
+    MinimumMse { bias: f64 },
+}
+
+impl RaveSchedule {
+    pub fn beta(&self, plays: f64, amaf_plays: f64) -> f64 {
+        match *self {
+            RaveSchedule::HandSelected { k } => {
+                f64::sqrt(k / (3.0 * plays + k))
+            }
+            RaveSchedule::MinimumMse { bias } => {
+                amaf_plays / (plays + amaf_plays
+                              + 4.0 * bias * bias * plays * amaf_plays)
+            }
+        }
+    }
+}
+
+impl Default for RaveSchedule {
+    fn default() -> Self {
+        RaveSchedule::HandSelected { k: 1000.0 }
+    }
+}
+
+// Scores children with `policy` after blending their mean with their AMAF
+// value. Needs MonteCarloAgent::amaf to collect the AMAF statistics.
+pub struct Rave {
+    pub policy: Box<dyn SelectionPolicy>,
+    pub schedule: RaveSchedule,
+}
+
+impl Rave {
+    pub fn new(policy: impl SelectionPolicy + 'static,
+               schedule: RaveSchedule) -> Self {
+        Self {
+            policy: Box::new(policy),
+            schedule: schedule,
+        }
+    }
+}
+
+impl SelectionPolicy for Rave {
+    fn score(&self, parent: &NodeStats, child: &NodeStats,
+             rng: &mut dyn RngCore) -> f64 {
+        if child.plays == 0.0 || child.amaf_plays == 0.0 {
+            return self.policy.score(parent, child, rng);
+        }
+        let beta = self.schedule.beta(child.plays, child.amaf_plays);
+        let amaf = child.amaf_wins / child.amaf_plays;
+        let mut blended = child.clone();
+        blended.wins = ((1.0 - beta) * mean(child) + beta * amaf)
+            * child.plays;
+        self.policy.score(parent, &blended, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(wins: f64, plays: f64) -> NodeStats {
+        let mut node = NodeStats::new();
+        node.wins = wins;
+        node.sq_wins = wins;
+        node.plays = plays;
+        node
+    }
+
+    #[test]
+    fn test_unvisited_first() {
+        let parent = node(5.0, 10.0);
+        let rng = &mut rand::thread_rng();
+        assert_eq!(Ucb1::default().score(&parent, &node(0.0, 0.0), rng),
+                   f64::INFINITY);
+        assert_eq!(Ucb1Tuned.score(&parent, &node(0.0, 0.0), rng),
+                   f64::INFINITY);
+        assert_eq!(UcbV::default().score(&parent, &node(0.0, 0.0), rng),
+                   f64::INFINITY);
+    }
+
+    #[test]
+    fn test_ucb1() {
+        let parent = node(5.0, 10.0);
+        let rng = &mut rand::thread_rng();
+        let ucb = Ucb1 { c: 0.0 };
+        assert_eq!(ucb.score(&parent, &node(3.0, 4.0), rng), 0.75);
+        let ucb = Ucb1::default();
+        let better = ucb.score(&parent, &node(3.0, 4.0), rng);
+        let explored = ucb.score(&parent, &node(6.0, 8.0), rng);
+        assert!(better > explored);
+    }
+
+    #[test]
+    fn test_puct_prior() {
+        let parent = node(5.0, 10.0);
+        let rng = &mut rand::thread_rng();
+        let mut likely = node(1.0, 2.0);
+        likely.prior = 0.9;
+        let mut unlikely = node(1.0, 2.0);
+        unlikely.prior = 0.1;
+        let puct = Puct::default();
+        assert!(puct.score(&parent, &likely, rng)
+                > puct.score(&parent, &unlikely, rng));
+    }
+
+    #[test]
+    fn test_thompson_sampling() {
+        let parent = node(50.0, 100.0);
+        let rng = &mut rand::thread_rng();
+        for _ in 0..10 {
+            let score = ThompsonSampling.score(&parent, &node(49.0, 50.0), rng);
+            assert!(score > 0.0 && score < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_rave() {
+        let parent = node(5.0, 10.0);
+        let rng = &mut rand::thread_rng();
+        let schedule = RaveSchedule::HandSelected { k: 1.0 };
+        assert_eq!(schedule.beta(0.0, 5.0), 1.0);
+        assert_eq!(schedule.beta(1.0, 5.0), 0.5);
+        let rave = Rave::new(Ucb1 { c: 0.0 }, schedule);
+        let mut child = node(0.0, 1.0);
+        child.amaf_wins = 4.0;
+        child.amaf_plays = 4.0;
+        assert_eq!(rave.score(&parent, &child, rng), 0.5);
+        let schedule = RaveSchedule::MinimumMse { bias: 0.0 };
+        assert_eq!(schedule.beta(2.0, 6.0), 0.75);
+    }
+}
@@ -0,0 +1,282 @@
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::game::*;
+use crate::tree::*;
+use crate::tree_node::*;
+
+// Saved trees start with MAGIC and the version in the binary format and
+// carry the version in JSON. Bump the version whenever Snapshot or the types
+// in it change.
+pub const SNAPSHOT_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"MCTS";
+// Most that from_bytes decodes, so a shared file can't make it allocate
+// without bound.
+pub const MAX_SNAPSHOT_BYTES: u64 = 1 << 30;
+
+// Everything about a NodeTree but its states. The root state is given back
+// when loading, see NodeTree::from_json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot<A> {
+    pub version: u32,
+    pub nodes: Vec<Node<A>>,
+    pub stats: Vec<NodeStats>,
+    pub positions: Vec<NodeStats>,
+    // Transposition table as (hash, node) pairs
+    pub table: Vec<(u64, NodeId)>,
+    pub state_cache: StateCache,
+    pub transpositions: bool,
+}
+
+impl<A> Snapshot<A> {
+    // Makes sure walking the tree terminates: parents come before their
+    // children, only the root has none, children point back to their parent
+    // and links lead to nodes that don't link on.
+    fn check(self) -> Result<Self, SnapshotError> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(self.version));
+        }
+        let len = self.nodes.len();
+        let in_tree = |node_id: &NodeId| *node_id < len;
+        let valid = |(node_id, node): (NodeId, &Node<A>)| {
+            let parent_ok = match node.parent {
+                Some(parent_id) => parent_id < node_id,
+                None => node_id == 0,
+            };
+            let link_ok = node.link.map_or(true, |link| {
+                in_tree(&link) && link != node_id
+                    && self.nodes[link].link.is_none()
+            });
+            let children_ok = node.first_child.checked_add(node.num_children)
+                .map_or(false, |end| end <= len)
+                && node.children().all(|child_id| {
+                    self.nodes[child_id].parent == Some(node_id)
+                });
+            parent_ok && link_ok && children_ok
+        };
+        if len == 0 || self.stats.len() != len || self.positions.len() != len
+            || !self.table.iter().all(|(_, node_id)| in_tree(node_id))
+            || !self.nodes.iter().enumerate().all(valid) {
+            return Err(SnapshotError::Invalid);
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    // The data doesn't start with MAGIC
+    NotATree,
+    // Saved by a different version of the format
+    Version(u32),
+    // Decoded but doesn't describe a tree
+    Invalid,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Json(error) => write!(f, "bad JSON: {}", error),
+            SnapshotError::Binary(error) => write!(f, "bad data: {}", error),
+            SnapshotError::NotATree => write!(f, "not a saved tree"),
+            SnapshotError::Version(version) => {
+                write!(f, "tree saved in version {}, expected {}", version,
+                       SNAPSHOT_VERSION)
+            }
+            SnapshotError::Invalid => write!(f, "inconsistent tree"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(error: serde_json::Error) -> Self {
+        SnapshotError::Json(error)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(error: bincode::Error) -> Self {
+        SnapshotError::Binary(error)
+    }
+}
+
+impl<S: GameState> NodeTree<S>
+where S::Action: Serialize + DeserializeOwned {
+
+    // Pretty printed, for reading and diffing.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_snapshot()).unwrap()
+    }
+
+    // Loads a tree saved with to_json whose root has `root_state`.
+    pub fn from_json(json: &str, root_state: &S
+                     ) -> Result<Self, SnapshotError> {
+        let value = serde_json::from_str::<serde_json::Value>(json)?;
+        // Later versions may lay out the rest differently
+        let version = value.get("version")
+            .and_then(|version| version.as_u64())
+            .and_then(|version| u32::try_from(version).ok());
+        if let Some(version) = version {
+            if version != SNAPSHOT_VERSION {
+                return Err(SnapshotError::Version(version));
+            }
+        }
+        let snapshot =
+            serde_json::from_value::<Snapshot<S::Action>>(value)?;
+        Ok(Self::from_snapshot(snapshot.check()?, root_state))
+    }
+
+    // MAGIC, the version as a little endian u32 and then the snapshot in
+    // bincode with variable length integers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut bytes, &self.to_snapshot()).unwrap();
+        bytes
+    }
+
+    // Loads a tree saved with to_bytes whose root has `root_state`.
+    pub fn from_bytes(bytes: &[u8], root_state: &S
+                      ) -> Result<Self, SnapshotError> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(SnapshotError::NotATree);
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(version);
+        // Later versions may lay out the rest differently
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        let snapshot = bincode::DefaultOptions::new()
+            .with_limit(MAX_SNAPSHOT_BYTES)
+            .deserialize::<Snapshot<S::Action>>(&bytes[8..])?;
+        Ok(Self::from_snapshot(snapshot.check()?, root_state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::agent::*;
+    use crate::monte_carlo_agent::*;
+    use crate::search_config::*;
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    fn setup_tree() -> NodeTree<TicTacToeState> {
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        tree.transpositions = true;
+        tree.state_cache = StateCache::Every(2);
+        let state = tree.root_state.clone();
+        tree.expand(0, &state);
+        let state = tree.state(1);
+        tree.expand(1, &state);
+        tree.stats_mut(1).wins = 2.5;
+        tree.stats_mut(1).plays = 4.0;
+        tree.stats_mut(1).proof = Proof::Draw;
+        tree
+    }
+
+    fn assert_same(tree: &NodeTree<TicTacToeState>,
+                   loaded: &NodeTree<TicTacToeState>) {
+        assert_eq!(loaded.len(), tree.len());
+        for node_id in 0..tree.len() {
+            assert_eq!(loaded.stats(node_id), tree.stats(node_id));
+            assert_eq!(loaded.position_stats(node_id),
+                       tree.position_stats(node_id));
+            assert_eq!(loaded.get(node_id).action, tree.get(node_id).action);
+            assert_eq!(loaded.get(node_id).link, tree.get(node_id).link);
+            assert_eq!(loaded.children(node_id), tree.children(node_id));
+            assert_eq!(loaded.state(node_id).board,
+                       tree.state(node_id).board);
+        }
+        assert_eq!(loaded.state_cache, tree.state_cache);
+        assert_eq!(loaded.transpositions, tree.transpositions);
+    }
+
+    #[test]
+    fn test_json() {
+        let tree = setup_tree();
+        let json = tree.to_json();
+        assert!(json.contains("\"version\": 1"));
+        let loaded = NodeTree::from_json(&json, &tree.root_state).unwrap();
+        assert_same(&tree, &loaded);
+        assert_eq!(loaded.to_json(), json);
+        assert!(NodeTree::from_json("{}", &tree.root_state).is_err());
+        let newer = r#"{"version": 2, "tree": []}"#;
+        assert!(matches!(NodeTree::from_json(newer, &tree.root_state),
+                         Err(SnapshotError::Version(2))));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let tree = setup_tree();
+        let mut value =
+            serde_json::from_str::<serde_json::Value>(&tree.to_json())
+                .unwrap();
+        value["nodes"][1]["first_child"] = usize::MAX.into();
+        value["nodes"][1]["num_children"] = 2.into();
+        let invalid = |value: &serde_json::Value| {
+            matches!(NodeTree::from_json(&value.to_string(), &tree.root_state),
+                     Err(SnapshotError::Invalid))
+        };
+        assert!(invalid(&value));
+        // Cycles through parents or links
+        let mut value =
+            serde_json::from_str::<serde_json::Value>(&tree.to_json())
+                .unwrap();
+        value["nodes"][1]["parent"] = 2.into();
+        assert!(invalid(&value));
+        let mut value =
+            serde_json::from_str::<serde_json::Value>(&tree.to_json())
+                .unwrap();
+        value["nodes"][2]["link"] = 3.into();
+        value["nodes"][3]["link"] = 2.into();
+        assert!(invalid(&value));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let tree = setup_tree();
+        let bytes = tree.to_bytes();
+        assert_eq!(&bytes[..8], b"MCTS\x01\0\0\0");
+        let loaded = NodeTree::from_bytes(&bytes, &tree.root_state).unwrap();
+        assert_same(&tree, &loaded);
+        assert!(bytes.len() < tree.to_json().len() / 4);
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(matches!(NodeTree::from_bytes(&newer, &tree.root_state),
+                         Err(SnapshotError::Version(2))));
+        assert!(matches!(NodeTree::from_bytes(b"{}", &tree.root_state),
+                         Err(SnapshotError::NotATree)));
+        let truncated = &bytes[..bytes.len() / 2];
+        assert!(NodeTree::from_bytes(truncated, &tree.root_state).is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut state = TicTacToeState::new();
+        state.board = [1, 1, 0,
+                       0, 0, 0,
+                       2, 2, 0];
+        let mut agent = MonteCarloAgent::new()
+            .with_config(SearchConfig::new().iterations(100))
+            .with_tree_reuse(true);
+        agent.choose_action(&state);
+        let bytes = agent.tree().unwrap().to_bytes();
+        agent.reset();
+        let tree = NodeTree::from_bytes(&bytes, &state).unwrap();
+        assert_eq!(tree.stats(0).plays, 100.0);
+        agent.set_tree(tree);
+        assert_eq!(agent.choose_action(&state), Some(2));
+        assert_eq!(agent.tree().unwrap().stats(0).plays, 200.0);
+    }
+}
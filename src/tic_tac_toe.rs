@@ -1,26 +1,48 @@
+use crate::agent::*;
 use crate::game::*;
 use crate::random_agent::*;
 
-use crate::p;
+// The 8 rotations and reflections of the board. Cell i of the transformed
+// board is cell SYMMETRIES[k][i] of the original.
+const SYMMETRIES: [[usize; 9]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8], // identity
+    [6, 3, 0, 7, 4, 1, 8, 5, 2], // rotations
+    [8, 7, 6, 5, 4, 3, 2, 1, 0],
+    [2, 5, 8, 1, 4, 7, 0, 3, 6],
+    [2, 1, 0, 5, 4, 3, 8, 7, 6], // reflections
+    [6, 7, 8, 3, 4, 5, 0, 1, 2],
+    [0, 3, 6, 1, 4, 7, 2, 5, 8],
+    [8, 5, 2, 7, 4, 1, 6, 3, 0],
+];
 
 #[derive(Debug, Clone)]
 pub struct TicTacToeState {
     pub board: [usize; 9],
     pub player: usize,
-    pub players: Vec<RandomAgent>,
+    // Indexed by player, slot 0 is unused
+    pub players: Vec<SharedAgent<TicTacToeState>>,
 }
 
 impl GameState for TicTacToeState {
+    type Action = usize;
 
     fn new() -> Self {
         Self {
             board: [0, 0, 0, 0, 0, 0, 0, 0, 0],
             player: 1,
-            players: vec![RandomAgent{}, RandomAgent{}, RandomAgent{}],
+            players: vec![
+                shared_agent(RandomAgent::new()),
+                shared_agent(RandomAgent::new()),
+                shared_agent(RandomAgent::new()),
+            ],
         }
     }
 
-    fn reward(&self) -> Option<f64> {
+    fn player(&self) -> usize {
+        self.player
+    }
+
+    fn rewards(&self) -> Option<Vec<f64>> {
         fn is_winner(cells: &[usize]) -> bool {
             cells.iter().all(|&cell| cell == cells[0] && cell > 0)
         }
@@ -35,17 +57,19 @@ impl GameState for TicTacToeState {
             [board[0], board[4], board[8]], // diags
             [board[2], board[4], board[6]],
         ];
+        // Slot 0 isn't a player and always scores zero
+        let mut rewards = vec![0.0; self.players.len()];
         for positions in win_positions.iter() {
             if is_winner(positions) {
-                if positions[0] == self.player {
-                    return Some(1.0);
-                } else {
-                    return Some(0.0);
-                }
+                rewards[positions[0]] = 1.0;
+                return Some(rewards);
             }
         }
         if board.iter().all(|&cell| cell > 0) {
-            return Some(0.5);
+            for reward in rewards.iter_mut().skip(1) {
+                *reward = 0.5;
+            }
+            return Some(rewards);
         } else {
             return None;
         }
@@ -70,13 +94,26 @@ impl GameState for TicTacToeState {
             }
         }).collect();
         println!("{:?} Player: {}", &board[0..3], self.player);
-        p!(&board[3..6]);
-        p!(&board[6..9]);
+        println!("{:?}", &board[3..6]);
+        println!("{:?}", &board[6..9]);
+    }
+
+    // Rows separated by slashes, then the player to move
+    fn render(&self) -> String {
+        let cells: String = self.board.iter().map(|&i| {
+            match i {
+                1 => '1',
+                2 => '2',
+                _ => '.',
+            }
+        }).collect();
+        format!("{}/{}/{} {}", &cells[0..3], &cells[3..6], &cells[6..9],
+                self.player)
     }
 
-    fn next_state(&self, action: usize) -> TicTacToeState {
+    fn next_state(&self, action: &usize) -> TicTacToeState {
         let mut new_state = self.clone();
-        new_state.board[action] = self.player;
+        new_state.board[*action] = self.player;
         if self.player == self.players.len()-1 {
             new_state.player = 1;
         } else {
@@ -84,12 +121,48 @@ impl GameState for TicTacToeState {
         }
         new_state
     }
+
+    // The board in base 3 with the player to move on top
+    fn hash(&self) -> Option<u64> {
+        let board = self.board.iter()
+            .fold(0, |hash, &cell| hash * 3 + cell as u64);
+        Some(board * 3 + self.player as u64)
+    }
+
+    // The symmetry with the lowest board
+    fn canonical_form(&self) -> Option<(Self, ActionMap<Self>)> {
+        let symmetry = SYMMETRIES.iter()
+            .min_by_key(|symmetry| symmetry.map(|cell| self.board[cell]))
+            .unwrap();
+        let mut canonical = self.clone();
+        canonical.board = symmetry.map(|cell| self.board[cell]);
+        let actions = canonical.legal_actions().into_iter()
+            .map(|action| (action, symmetry[action]))
+            .collect();
+        Some((canonical, actions))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use rand::seq::SliceRandom;
+    use rand::RngCore;
+
     use super::*;
 
+    // Plays uniformly random moves until the game is over.
+    fn simulate(state: &TicTacToeState, mut rng: &mut dyn RngCore
+                ) -> TicTacToeState {
+        let mut state = state.clone();
+        let mut actions = state.legal_actions();
+        while !actions.is_empty() {
+            let action = actions.choose(&mut rng).unwrap();
+            state = state.next_state(action);
+            actions = state.legal_actions();
+        }
+        state
+    }
+
     #[test]
     fn test_reward() {
         let mut state = TicTacToeState::new();
@@ -133,6 +206,47 @@ mod tests {
         assert_eq!(state.reward(), Some(1.0));
     }
 
+    #[test]
+    fn test_rewards() {
+        let mut state = TicTacToeState::new();
+        state.board = [2, 2, 2,
+                       1, 1, 2,
+                       2, 1, 1];
+        assert_eq!(state.rewards(), Some(vec![0.0, 0.0, 1.0]));
+        state.board = [2, 1, 2,
+                       1, 1, 2,
+                       2, 2, 1];
+        assert_eq!(state.rewards(), Some(vec![0.0, 0.5, 0.5]));
+        state.board = [0, 0, 0,
+                       0, 0, 0,
+                       0, 0, 0];
+        assert_eq!(state.rewards(), None);
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let mut state = TicTacToeState::new();
+        state.board = [1, 2, 0,
+                       0, 0, 0,
+                       0, 0, 0];
+        let (canonical, actions) = state.canonical_form().unwrap();
+        for board in [[0, 0, 1, 0, 0, 2, 0, 0, 0],
+                      [0, 0, 0, 0, 0, 0, 0, 2, 1],
+                      [1, 0, 0, 2, 0, 0, 0, 0, 0]].iter() {
+            let mut symmetric = state.clone();
+            symmetric.board = *board;
+            assert_eq!(symmetric.canonical_form().unwrap().0.board,
+                       canonical.board);
+        }
+        // Actions map back to the same position
+        assert_eq!(actions.len(), 7);
+        for (action, original) in actions.iter() {
+            let next = canonical.next_state(action).canonical_form();
+            assert_eq!(state.next_state(original).canonical_form()
+                       .unwrap().0.board, next.unwrap().0.board);
+        }
+    }
+
     #[test]
     fn test_simulate() {
         let mut state = TicTacToeState::new();
@@ -142,6 +256,7 @@ mod tests {
                        2, 1, 1];
         let mut comp_state = state.clone();
         comp_state.board[0] = 1;
-        assert_eq!(state.simulate().board, comp_state.board);
+        let rng = &mut rand::thread_rng();
+        assert_eq!(simulate(&state, rng).board, comp_state.board);
     }
 }
@@ -1,12 +1,82 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::sync::Mutex;
+
 use crate::game::*;
 use crate::tree_node::*;
 
-use crate::p;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use crate::snapshot::*;
+
+// Which node states NodeTree keeps around instead of replaying actions from
+// the root. Every(k) stores the state of nodes at depths that are multiples
+// of k, so Every(1) stores all of them. Every(0) stores none, like Off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StateCache {
+    Off,
+    Every(usize),
+}
+
+impl StateCache {
+    pub fn caches(&self, depth: usize) -> bool {
+        match *self {
+            StateCache::Off => false,
+            StateCache::Every(k) => k > 0 && depth > 0 && depth % k == 0,
+        }
+    }
+}
+
+// Moves from the root as played in the states along the way, see
+// NodeTree::game_action.
+#[derive(Debug, Clone)]
+pub struct Line<S: GameState> {
+    pub actions: Vec<S::Action>,
+    // The state at the end of the line
+    pub state: S,
+    // The node at the end of the line
+    pub node_id: NodeId,
+}
+
+// The children of a node worked out by NodeTree::prepare_expansion and
+// added by NodeTree::publish_expansion.
+#[derive(Debug, Clone)]
+pub struct Expansion<S: GameState> {
+    // The node holding the position expanded
+    node_id: NodeId,
+    player: usize,
+    // Action, cached state, position hash and prior of each child
+    children: Vec<(S::Action, Option<S>, Option<u64>, f64)>,
+}
 
+// Searching threads share a NodeTree behind a lock, reading it while they
+// select, expand and back up and writing it only to add the nodes of an
+// expansion. Statistics are locked per node so threads can update them
+// with only read access.
+//
+// With transpositions on, expand links children whose position is already
+// in the tree to the node holding it, which turns the tree into a DAG.
+// Every node then has statistics for the edge from its parent, in stats,
+// and for its position, in positions, summed over all edges leading there.
+// Positions must not repeat within a game.
+//
+// Games with a GameState::canonical_form are expanded from the canonical
+// form of a node's state, so symmetric positions are linked too. The actions
+// of children are then in the orientation of the canonical form, see
+// game_action and next_state.
 #[derive(Debug)]
 pub struct NodeTree<S: GameState> {
-    nodes: Vec<Node>,
-    pub pprint_mode: usize,
+    nodes: Vec<Node<S::Action>>,
+    stats: Vec<Mutex<NodeStats>>,
+    positions: Vec<Mutex<NodeStats>>,
+    states: Vec<Option<S>>,
+    // Position hash to the node holding the position
+    table: HashMap<u64, NodeId>,
+    pub state_cache: StateCache,
+    pub transpositions: bool,
     pub root_state: S,
 }
 
@@ -14,97 +84,477 @@ impl<S: GameState> NodeTree<S> {
 
     pub fn new(root_state: &S) -> Self {
         Self {
-            nodes: vec![Node::new(0, None)],
-            pprint_mode: 0,
+            nodes: vec![Node::new(None, None)],
+            stats: vec![Mutex::new(NodeStats::new())],
+            positions: vec![Mutex::new(NodeStats::new())],
+            states: vec![None],
+            table: HashMap::new(),
+            state_cache: StateCache::Off,
+            transpositions: false,
             root_state: root_state.clone(),
         }
     }
 
-    pub fn add_node(&mut self, action: NodeAction, parent_id: NodeId) -> NodeId {
+    // Adds a single child. A node's children have to stay next to each
+    // other, so this panics unless parent_id has no children yet or its
+    // children are the last nodes added. Never links to transpositions.
+    // The player and prior are set from the parent's state like in expand.
+    pub fn add_node(&mut self, action: S::Action, parent_id: NodeId) -> NodeId {
         let node_id = self.nodes.len() as NodeId;
         let parent = self.get_mut(parent_id);
-        if parent.first_child.is_none() {
-            parent.first_child = Some(node_id);
-        } else {
-            // Only call this if parent has children
-            let last_child_id = self.get_last_child_id(parent_id);
-            let last_child = self.get_mut(last_child_id);
-            last_child.next_sibling = Some(node_id);
+        if parent.num_children == 0 {
+            parent.first_child = node_id;
+        } else if parent.first_child + parent.num_children != node_id {
+            panic!("children of node {} have to be added together", parent_id);
         }
-        self.nodes.push(Node::new(action, Some(parent_id)));
+        parent.num_children += 1;
+        let parent_state = self.state(parent_id);
+        let state = if self.state_cache.caches(self.get(parent_id).depth + 1) {
+            Some(self.next_state(&parent_state, &action))
+        } else {
+            None
+        };
+        let prior = parent_state.prior(&self.game_action(&parent_state,
+                                                         &action));
+        self.push_node(action, parent_id, state);
+        self.get_mut(node_id).player = parent_state.player();
+        self.stats_mut(node_id).prior = prior;
         node_id
     }
 
-    pub fn get_last_child_id(&self, node_id: NodeId) -> NodeId {
-        let parent = self.get(node_id);
-        let mut child_id = parent.first_child.unwrap();
-        loop {
-            let child = self.get(child_id);
-            match child.next_sibling {
-                Some(id) => child_id = id,
-                None => break,
+    // Adds a child for every legal action in `state`, the state of node_id,
+    // and returns their ids.
+    pub fn expand(&mut self, node_id: NodeId, state: &S) -> Range<NodeId> {
+        let expansion = self.prepare_expansion(node_id, state);
+        match self.publish_expansion(expansion) {
+            Some(children) => children,
+            None => panic!("node {} is already expanded", node_id),
+        }
+    }
+
+    // Works out the children expand adds without changing the tree, so
+    // threads sharing it only need to read it for that.
+    pub fn prepare_expansion(&self, node_id: NodeId, state: &S
+                             ) -> Expansion<S> {
+        let node_id = self.position(node_id);
+        let caches = self.state_cache.caches(self.get(node_id).depth + 1);
+        // Reached through a link `state` may be a symmetric one, cached
+        // states follow the parents of the node instead
+        let own_state;
+        let state = if caches && self.transpositions {
+            own_state = self.state(node_id);
+            &own_state
+        } else {
+            state
+        };
+        let mut children = Vec::new();
+        for action in self.legal_actions(state) {
+            let child_state = if caches || self.transpositions {
+                Some(self.next_state(state, &action))
+            } else {
+                None
+            };
+            let hash = match (self.transpositions, &child_state) {
+                (true, Some(child_state)) => Self::position_hash(child_state),
+                _ => None,
+            };
+            let prior = state.prior(&self.game_action(state, &action));
+            let child_state = if caches { child_state } else { None };
+            children.push((action, child_state, hash, prior));
+        }
+        Expansion {
+            node_id: node_id,
+            player: state.player(),
+            children: children,
+        }
+    }
+
+    // Adds the children of a prepared expansion and returns their ids, or
+    // None if the node got children since it was prepared.
+    pub fn publish_expansion(&mut self, expansion: Expansion<S>
+                             ) -> Option<Range<NodeId>> {
+        let node_id = expansion.node_id;
+        if self.get(node_id).num_children > 0 {
+            return None;
+        }
+        let first_child = self.nodes.len() as NodeId;
+        for (action, child_state, hash, prior) in expansion.children {
+            let child_id = self.push_node(action, node_id, child_state);
+            self.get_mut(child_id).player = expansion.player;
+            self.stats_mut(child_id).prior = prior;
+            if let Some(hash) = hash {
+                let link = *self.table.entry(hash).or_insert(child_id);
+                if link != child_id {
+                    self.get_mut(child_id).link = Some(link);
+                }
             }
         }
-        child_id
+        let num_children = self.nodes.len() - first_child;
+        let node = self.get_mut(node_id);
+        node.first_child = first_child;
+        node.num_children = num_children;
+        Some(self.children(node_id))
+    }
+
+    fn position_hash(state: &S) -> Option<u64> {
+        match state.canonical_form() {
+            Some((canonical, _)) => canonical.hash(),
+            None => state.hash(),
+        }
+    }
+
+    fn canonical_form(&self, state: &S) -> Option<(S, ActionMap<S>)> {
+        if self.transpositions {
+            state.canonical_form()
+        } else {
+            None
+        }
+    }
+
+    // The actions children of a node with `state` can have.
+    pub fn legal_actions(&self, state: &S) -> Vec<S::Action> {
+        match self.canonical_form(state) {
+            Some((canonical, _)) => canonical.legal_actions(),
+            None => state.legal_actions(),
+        }
+    }
+
+    // The action in `state` that a child's action stands for.
+    pub fn game_action(&self, state: &S, action: &S::Action) -> S::Action {
+        match self.canonical_form(state) {
+            Some((_, actions)) => actions[action].clone(),
+            None => action.clone(),
+        }
+    }
+
+    // The child action standing for an action in `state`, None if the
+    // action isn't legal there.
+    pub fn tree_action(&self, state: &S, action: &S::Action
+                       ) -> Option<S::Action> {
+        match self.canonical_form(state) {
+            Some((_, actions)) => actions.into_iter()
+                .find(|(_, game_action)| game_action == action)
+                .map(|(tree_action, _)| tree_action),
+            None => Some(action.clone()),
+        }
+    }
+
+    // The state a child's action leads to from `state`.
+    pub fn next_state(&self, state: &S, action: &S::Action) -> S {
+        state.next_state(&self.game_action(state, action))
+    }
+
+    fn push_node(&mut self, action: S::Action, parent_id: NodeId,
+                 state: Option<S>) -> NodeId {
+        let mut node = Node::new(Some(action), Some(parent_id));
+        node.depth = self.get(parent_id).depth + 1;
+        self.nodes.push(node);
+        self.stats.push(Mutex::new(NodeStats::new()));
+        self.positions.push(Mutex::new(NodeStats::new()));
+        self.states.push(state);
+        self.nodes.len() - 1
     }
 
-    pub fn get(&self, node_id: NodeId) -> &Node {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn get(&self, node_id: NodeId) -> &Node<S::Action> {
         &self.nodes[node_id]
     }
 
-    pub fn get_mut(&mut self, node_id: NodeId) -> &mut Node {
+    pub fn get_mut(&mut self, node_id: NodeId) -> &mut Node<S::Action> {
         &mut self.nodes[node_id]
     }
 
+    // A copy, other threads may be updating them.
+    pub fn stats(&self, node_id: NodeId) -> NodeStats {
+        self.stats[node_id].lock().unwrap().clone()
+    }
+
+    pub fn stats_mut(&mut self, node_id: NodeId) -> &mut NodeStats {
+        self.stats[node_id].get_mut().unwrap()
+    }
+
+    // The node holding the position node_id leads to.
+    pub fn position(&self, node_id: NodeId) -> NodeId {
+        self.get(node_id).link.unwrap_or(node_id)
+    }
+
+    // Statistics of the position node_id leads to, over all edges leading
+    // there. The same as stats without transpositions.
+    pub fn position_stats(&self, node_id: NodeId) -> NodeStats {
+        if !self.transpositions {
+            return self.stats(node_id);
+        }
+        self.positions[self.position(node_id)].lock().unwrap().clone()
+    }
+
+    // The statistics to score node_id with during selection: the plays of
+    // its edge with the mean reward of its position.
+    pub fn edge_stats(&self, node_id: NodeId) -> NodeStats {
+        let mut stats = self.stats(node_id);
+        if self.transpositions {
+            let position = self.position_stats(node_id);
+            if position.plays > 0.0 {
+                stats.wins = position.wins / position.plays * stats.plays;
+                stats.sq_wins = position.sq_wins / position.plays
+                    * stats.plays;
+            }
+        }
+        stats
+    }
+
+    pub fn update_position_stats(&self, node_id: NodeId,
+                                 f: impl FnOnce(&mut NodeStats)) {
+        if self.transpositions {
+            f(&mut self.positions[self.position(node_id)].lock().unwrap())
+        }
+    }
+
+    // Changes the stats of node_id without exclusive access to the tree.
+    pub fn update_stats(&self, node_id: NodeId,
+                        f: impl FnOnce(&mut NodeStats)) {
+        f(&mut self.stats[node_id].lock().unwrap())
+    }
+
+    // The tree with the default TreeFormatter, see Display.
     pub fn pretty_print(&self) {
-        self.pprint_inner(0, "".to_string(), true)
-    }
-    fn pprint_inner(&self, node_id: NodeId, prefix: String, last: bool) {
-        let node = self.get(node_id);
-        let prefix_current = if last { "`- " } else { "|- " };
-
-        if self.pprint_mode == 1 {
-            println!("{}{}wins:{} plays:{}", prefix,
-                     prefix_current, node.wins, node.plays);
-        } else if self.pprint_mode == 2 {
-            println!("{}{}id:{} next_sibling:{:?}", prefix,
-                     prefix_current, node_id, node.next_sibling);
-        } else if self.pprint_mode == 3 {
-            println!("{}{}id:{} parent:{:?}", prefix,
-                     prefix_current, node_id, node.parent);
-        } else {
-            println!("{}{}id:{} action:{} wins:{} plays:{}",
-                     prefix, prefix_current,
-                     node_id, node.action, node.wins, node.plays);
+        print!("{}", self)
+    }
+
+    // UCT score of the edge to node_id, None for the root and unvisited
+    // edges. `c` is the exploration constant, see Ucb1.
+    pub fn uct(&self, node_id: NodeId, c: f64) -> Option<f64> {
+        let parent = self.position_stats(self.get(node_id).parent?);
+        let edge = self.edge_stats(node_id);
+        if edge.plays == 0.0 {
+            return None;
         }
+        Some(edge.wins / edge.plays
+             + c * f64::sqrt(f64::ln(parent.plays) / edge.plays))
+    }
+
+    // Children of the position node_id leads to, see position.
+    pub fn children(&self, node_id: NodeId) -> Range<NodeId> {
+        self.get(self.position(node_id)).children()
+    }
+
+    pub fn has_children(&self, node_id: NodeId) -> bool {
+        self.get(self.position(node_id)).num_children > 0
+    }
 
-        let prefix_child = if last { "   " } else { "|  " };
-        let prefix = prefix + prefix_child;
+    pub fn find_child(&self, node_id: NodeId, action: &S::Action
+                      ) -> Option<NodeId> {
+        self.children(node_id).find(|&child_id| {
+            self.get(child_id).action.as_ref() == Some(action)
+        })
+    }
+
+    // The line following the most visited children from the root, at most
+    // `depth` moves long.
+    pub fn principal_line(&self, depth: usize) -> Line<S> {
+        self.extend_line(self.root_line(), depth)
+    }
+
+    pub fn principal_variation(&self, depth: usize) -> Vec<S::Action> {
+        self.principal_line(depth).actions
+    }
 
-        let mut opt_child_id = node.first_child;
-        while let Some(child_id) = opt_child_id {
-            self.pprint_inner(child_id, prefix.to_string(),
-                opt_child_id.is_none());
-            opt_child_id = self.get(child_id).next_sibling;
+    // Lines starting with each of the k most visited root children, best
+    // first, each continued with the most visited children up to `depth`
+    // moves.
+    pub fn best_lines(&self, k: usize, depth: usize) -> Vec<Line<S>> {
+        if depth == 0 {
+            return Vec::new();
         }
+        let mut children = self.children(0).collect::<Vec<NodeId>>();
+        children.sort_by(|&a, &b| {
+            self.stats(b).plays.partial_cmp(&self.stats(a).plays).unwrap()
+        });
+        children.into_iter()
+            .filter(|&child_id| self.stats(child_id).plays > 0.0)
+            .take(k)
+            .map(|child_id| {
+                let line = self.push_move(self.root_line(), child_id);
+                self.extend_line(line, depth)
+            })
+            .collect()
     }
 
-    pub fn children(&self, node_id: NodeId) -> NodeChildIterator<S> {
-        NodeChildIterator::new(&self, node_id)
+    fn root_line(&self) -> Line<S> {
+        Line {
+            actions: Vec::new(),
+            state: self.root_state.clone(),
+            node_id: 0,
+        }
     }
 
-    pub fn state(&self, node_id: NodeId) -> S {
-        let mut state = self.root_state.clone();
-        let mut actions = Vec::new();
-        // Collect actions
-        let mut node = self.get(node_id);
-        while let Some(par_id) = node.parent {
-            actions.push(node.action);
-            node = self.get(par_id);
+    fn extend_line(&self, mut line: Line<S>, depth: usize) -> Line<S> {
+        while line.actions.len() < depth {
+            let best = self.children(line.node_id).max_by(|&a, &b| {
+                self.stats(a).plays.partial_cmp(&self.stats(b).plays).unwrap()
+            });
+            match best {
+                Some(best) if self.stats(best).plays > 0.0 => {
+                    line = self.push_move(line, best);
+                }
+                _ => break,
+            }
+        }
+        line
+    }
+
+    // Plays the action of child_id, a child of the line's last node.
+    fn push_move(&self, mut line: Line<S>, child_id: NodeId) -> Line<S> {
+        let action = self.get(child_id).action.as_ref().unwrap();
+        line.actions.push(self.game_action(&line.state, action));
+        line.state = self.next_state(&line.state, action);
+        line.node_id = child_id;
+        line
+    }
+
+    // Makes node_id the new root, dropping everything outside its subtree.
+    // Remaining nodes are renumbered in breadth first order so the root is
+    // 0 again and children stay next to each other.
+    pub fn reroot(&mut self, node_id: NodeId) {
+        let root_state = self.state(node_id);
+        let mut order = vec![node_id];
+        let mut adopted = Vec::new();
+        let mut i = 0;
+        while i < order.len() {
+            // Links to positions outside the new tree take them over
+            if let Some(link) = self.get(order[i]).link {
+                let link = self.resolve(link);
+                if !self.descends_from(link, node_id) {
+                    self.adopt(order[i], link);
+                    adopted.push(order[i]);
+                } else {
+                    self.get_mut(order[i]).link = Some(link);
+                }
+            }
+            order.extend(self.get(order[i]).children());
+            i += 1;
+        }
+        let mut new_ids = vec![None; self.nodes.len()];
+        for (new_id, &old_id) in order.iter().enumerate() {
+            new_ids[old_id] = Some(new_id as NodeId);
         }
+        let table = mem::take(&mut self.table);
+        self.table = table.into_iter()
+            .filter_map(|(hash, id)| Some((hash, new_ids[self.resolve(id)]?)))
+            .collect();
+        let mut old_nodes = mem::take(&mut self.nodes).into_iter()
+            .map(Some).collect::<Vec<_>>();
+        let mut old_stats = mem::take(&mut self.stats).into_iter()
+            .map(Some).collect::<Vec<_>>();
+        let mut old_positions = mem::take(&mut self.positions).into_iter()
+            .map(Some).collect::<Vec<_>>();
+        let mut old_states = mem::take(&mut self.states);
+        for old_id in order {
+            let mut node = old_nodes[old_id].take().unwrap();
+            node.parent = node.parent.and_then(|id| new_ids[id]);
+            if node.num_children > 0 {
+                node.first_child = new_ids[node.first_child].unwrap();
+            }
+            node.link = node.link.map(|id| new_ids[id].unwrap());
+            node.depth = match node.parent {
+                Some(parent_id) => self.get(parent_id).depth + 1,
+                None => 0,
+            };
+            self.nodes.push(node);
+            self.stats.push(old_stats[old_id].take().unwrap());
+            self.positions.push(old_positions[old_id].take().unwrap());
+            self.states.push(old_states[old_id].take());
+        }
+        let root = self.get_mut(0);
+        root.parent = None;
+        root.action = None;
+        self.states[0] = None;
+        self.root_state = root_state;
+        // States cached below adopted nodes are in the orientation of their
+        // old parents, redo them along the new ones
+        let mut moved = vec![false; self.nodes.len()];
+        for old_id in adopted {
+            moved[new_ids[old_id].unwrap()] = true;
+        }
+        for node_id in 1..self.nodes.len() {
+            let parent_id = self.get(node_id).parent.unwrap();
+            moved[node_id] |= moved[parent_id];
+            if moved[node_id] && self.states[node_id].is_some() {
+                let action = self.get(node_id).action.as_ref().unwrap();
+                let state = self.next_state(&self.state(parent_id), action);
+                self.states[node_id] = Some(state);
+            }
+        }
+    }
+
+    // Follows links from nodes that were taken over, see adopt.
+    fn resolve(&self, mut node_id: NodeId) -> NodeId {
+        while let Some(link) = self.get(node_id).link {
+            node_id = link;
+        }
+        node_id
+    }
+
+    fn descends_from(&self, mut node_id: NodeId, ancestor_id: NodeId) -> bool {
+        loop {
+            if node_id == ancestor_id {
+                return true;
+            }
+            match self.get(node_id).parent {
+                Some(parent_id) => node_id = parent_id,
+                None => return false,
+            }
+        }
+    }
+
+    // Moves the children and position statistics of target_id to link_id,
+    // which links to it, and links target_id to link_id instead.
+    fn adopt(&mut self, link_id: NodeId, target_id: NodeId) {
+        let target = self.get_mut(target_id);
+        let children = target.children();
+        target.num_children = 0;
+        target.link = Some(link_id);
+        for child_id in children.clone() {
+            self.get_mut(child_id).parent = Some(link_id);
+        }
+        let link = self.get_mut(link_id);
+        link.first_child = children.start;
+        link.num_children = children.len();
+        link.link = None;
+        self.positions.swap(link_id, target_id);
+        // Proofs and bounds of the position are kept with its holder
+        let settled = self.stats(target_id);
+        let stats = self.stats_mut(link_id);
+        stats.proof = settled.proof;
+        stats.pessimistic = settled.pessimistic;
+        stats.optimistic = settled.optimistic;
+    }
+
+    pub fn cached_state(&self, node_id: NodeId) -> Option<&S> {
+        self.states[node_id].as_ref()
+    }
+
+    pub fn state(&self, mut node_id: NodeId) -> S {
+        let mut actions = Vec::new();
+        // Collect actions up to the closest cached state
+        let mut state = loop {
+            if let Some(state) = self.cached_state(node_id) {
+                break state.clone();
+            }
+            let node = self.get(node_id);
+            match node.parent {
+                Some(par_id) => {
+                    actions.push(node.action.as_ref().unwrap());
+                    node_id = par_id;
+                },
+                None => break self.root_state.clone(),
+            }
+        };
         // Then replay them
         for action in actions.iter().rev() {
-            state = state.next_state(*action);
+            state = self.next_state(&state, action);
         }
         state
     }
@@ -123,12 +573,17 @@ impl<S: GameState> NodeTree<S> {
 //         node.status == NodeStatus::Expanded
     }
 
+    pub fn is_expandable(&self, node_id: NodeId) -> bool {
+        !self.is_expanded(node_id)
+    }
+
     pub fn unexpanded_actions(&self, node_id: NodeId,
-                            state: &impl GameState) -> Vec<usize> {
-        let mut actions = state.legal_actions();
+                              state: &S) -> Vec<S::Action> {
+        let mut actions = self.legal_actions(state);
         for child_id in self.children(node_id) {
             let child = self.get(child_id);
-            if let Some(i) = actions.iter().position(|&d| d == child.action) {
+            if let Some(i) = actions.iter()
+                .position(|d| Some(d) == child.action.as_ref()) {
                 actions.remove(i);
             }
         }
@@ -136,37 +591,42 @@ impl<S: GameState> NodeTree<S> {
     }
 }
 
-pub struct NodeChildIterator<'a, S: GameState> {
-    tree: &'a NodeTree<S>,
-    curr: NodeId,
-    first: bool,
-}
+#[cfg(feature = "serde")]
+impl<S: GameState> NodeTree<S> {
 
-impl<'a, S: GameState> NodeChildIterator<'a, S> {
-    fn new(tree: &'a NodeTree<S>, node_id: NodeId) -> Self {
-        Self {
-            tree: tree,
-            curr: node_id,
-            first: true,
+    pub(crate) fn to_snapshot(&self) -> Snapshot<S::Action> {
+        let mut table = self.table.iter()
+            .map(|(&hash, &node_id)| (hash, node_id))
+            .collect::<Vec<(u64, NodeId)>>();
+        table.sort();
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            nodes: self.nodes.clone(),
+            stats: (0..self.len()).map(|id| self.stats(id)).collect(),
+            positions: self.positions.iter()
+                .map(|stats| stats.lock().unwrap().clone())
+                .collect(),
+            table: table,
+            state_cache: self.state_cache,
+            transpositions: self.transpositions,
         }
     }
-}
 
-impl<'a, S: GameState> Iterator for NodeChildIterator<'a, S> {
-    type Item = NodeId;
-    fn next(&mut self) -> Option<Self::Item> {
-        let node = self.tree.get(self.curr);
-        let mut next_id = node.next_sibling;
-        if self.first {
-            self.first = false;
-            next_id = node.first_child;
-        }
-        match next_id {
-            Some(id) => {
-                self.curr = id;
-                Some(id)
-            },
-            None => None
+    // Cached states aren't saved, they are replayed from `root_state` when
+    // needed.
+    pub(crate) fn from_snapshot(snapshot: Snapshot<S::Action>, root_state: &S
+                                ) -> Self {
+        let len = snapshot.nodes.len();
+        Self {
+            nodes: snapshot.nodes,
+            stats: snapshot.stats.into_iter().map(Mutex::new).collect(),
+            positions: snapshot.positions.into_iter().map(Mutex::new)
+                .collect(),
+            states: vec![None; len],
+            table: snapshot.table.into_iter().collect(),
+            state_cache: snapshot.state_cache,
+            transpositions: snapshot.transpositions,
+            root_state: root_state.clone(),
         }
     }
 }
@@ -193,6 +653,230 @@ mod tests {
         assert_eq!(state.board, [2, 1, 2, 1, 0, 0, 0, 0, 0]);
     }
 
+    #[test]
+    fn test_expand() {
+        let mut tree = setup_tree();
+        let children = tree.expand(0, &tree.root_state.clone());
+        assert_eq!(children, 1..10);
+        let state = tree.state(3);
+        assert_eq!(tree.expand(3, &state), 10..18);
+        assert_eq!(tree.children(3), 10..18);
+        assert_eq!(tree.get(10).player, 2);
+        assert_eq!(tree.get(10).action, Some(0));
+        assert_eq!(tree.stats(10).prior, 1.0 / 8.0);
+        assert_eq!(tree.state(10).board, [2, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(tree.unexpanded_actions(3, &state), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_publish_expansion() {
+        let mut tree = setup_tree();
+        let first = tree.prepare_expansion(0, &tree.root_state);
+        let second = tree.prepare_expansion(0, &tree.root_state);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.publish_expansion(first), Some(1..10));
+        assert_eq!(tree.get(1).player, 1);
+        assert_eq!(tree.stats(1).prior, 1.0 / 9.0);
+        // Another expansion of the same node is dropped
+        assert_eq!(tree.publish_expansion(second), None);
+        assert_eq!(tree.len(), 10);
+    }
+
+    #[test]
+    fn test_add_node() {
+        let mut tree = setup_tree();
+        let child_id = tree.add_node(4, 0);
+        let grandchild_id = tree.add_node(0, child_id);
+        assert_eq!(tree.get(child_id).player, 1);
+        assert_eq!(tree.stats(child_id).prior, 1.0 / 9.0);
+        assert_eq!(tree.get(grandchild_id).player, 2);
+        assert_eq!(tree.stats(grandchild_id).prior, 1.0 / 8.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_add_node_contiguous() {
+        let mut tree = setup_tree();
+        tree.add_node(0, 0);
+        tree.add_node(1, 1);
+        tree.add_node(2, 0);
+    }
+
+    #[test]
+    fn test_reroot() {
+        let mut tree = setup_tree();
+        tree.add_node(1, 0); // 1 (node id)
+        tree.add_node(2, 0); // 2
+        tree.add_node(3, 2); // 3
+        tree.add_node(4, 2); // 4
+        tree.add_node(5, 1); // 5
+        tree.add_node(6, 4); // 6
+        tree.stats_mut(4).plays = 3.0;
+        let state = tree.state(2);
+        tree.reroot(2);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.root_state.board, state.board);
+        assert_eq!(tree.get(0).parent, None);
+        assert_eq!(tree.get(0).action, None);
+        assert_eq!(tree.children(0).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(tree.get(2).action, Some(4));
+        assert_eq!(tree.stats(2).plays, 3.0);
+        assert_eq!(tree.children(2).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(tree.get(3).parent, Some(2));
+        assert_eq!(tree.state(3).board, [0, 0, 1, 0, 2, 0, 1, 0, 0]);
+    }
+
+    // Plays `actions` from node_id, expanding nodes on the way, and returns
+    // the node reached.
+    fn follow(tree: &mut NodeTree<TicTacToeState>, mut node_id: NodeId,
+              actions: &[usize]) -> NodeId {
+        let mut state = tree.state(node_id);
+        for action in actions.iter() {
+            if !tree.has_children(node_id) {
+                tree.expand(node_id, &state);
+            }
+            let action = tree.tree_action(&state, action).unwrap();
+            node_id = tree.find_child(node_id, &action).unwrap();
+            state = tree.next_state(&state, &action);
+        }
+        node_id
+    }
+
+    // Plays 4, 0, 1 and 1, 0, 4 from the root, which reach the same board
+    // without passing symmetric ones. Returns the nodes reached.
+    fn setup_transposition(tree: &mut NodeTree<TicTacToeState>
+                           ) -> (NodeId, NodeId) {
+        tree.transpositions = true;
+        let first = follow(tree, 0, &[4, 0, 1]);
+        let second = follow(tree, 0, &[1, 0, 4]);
+        (first, second)
+    }
+
+    #[test]
+    fn test_transpositions() {
+        let mut tree = setup_tree();
+        let (first, second) = setup_transposition(&mut tree);
+        // The first line can end up on a symmetric sibling
+        let first = tree.position(first);
+        assert_eq!(tree.get(first).link, None);
+        assert_eq!(tree.get(second).link, Some(first));
+        let state = tree.state(second);
+        let canonical = |state: TicTacToeState| {
+            state.canonical_form().unwrap().0.board
+        };
+        assert_eq!(canonical(state.clone()), canonical(tree.state(first)));
+        let children = tree.expand(second, &state);
+        assert_eq!(tree.children(first), children);
+        assert_eq!(tree.children(second), children);
+        assert_eq!(tree.get(children.start).parent, Some(first));
+        let action = tree.get(children.start).action.unwrap();
+        assert_eq!(tree.state(children.start).board,
+                   tree.next_state(&tree.state(first), &action).board);
+        tree.update_position_stats(first, |stats| stats.plays += 1.0);
+        tree.update_position_stats(second, |stats| stats.plays += 1.0);
+        assert_eq!(tree.position_stats(second).plays, 2.0);
+        assert_eq!(tree.stats(first).plays, 0.0);
+    }
+
+    #[test]
+    fn test_symmetries() {
+        let mut tree = setup_tree();
+        tree.transpositions = true;
+        let children = tree.expand(0, &tree.root_state.clone());
+        let links = children.clone()
+            .map(|child_id| tree.position(child_id))
+            .collect::<Vec<NodeId>>();
+        // Corners, edges and the center
+        assert_eq!(links, vec![1, 2, 1, 2, 5, 2, 1, 2, 1]);
+        // Both reach the same node through the first corner, whose state
+        // is symmetric to theirs
+        let node_id = follow(&mut tree, 0, &[6, 5]);
+        assert_eq!(follow(&mut tree, 0, &[2, 3]), node_id);
+        let state = tree.state(node_id);
+        assert_eq!(state.board, [1, 0, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(tree.legal_actions(&state).len(), 7);
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut tree = setup_tree();
+        tree.add_node(4, 0); // 1 (node id)
+        tree.add_node(0, 0); // 2
+        tree.add_node(8, 0); // 3
+        tree.add_node(1, 1); // 4
+        tree.add_node(2, 1); // 5
+        tree.add_node(6, 5); // 6
+        for (node_id, plays) in [(1, 10.0), (2, 5.0), (4, 3.0), (5, 6.0),
+                                 (6, 2.0)].iter() {
+            tree.stats_mut(*node_id).plays = *plays;
+        }
+        let line = tree.principal_line(10);
+        assert_eq!(line.actions, vec![4, 2, 6]);
+        assert_eq!(line.state.board, [0, 0, 2, 0, 1, 0, 1, 0, 0]);
+        assert_eq!(line.node_id, 6);
+        assert_eq!(tree.principal_variation(2), vec![4, 2]);
+        // Unvisited children don't start lines
+        let lines = tree.best_lines(5, 2);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].actions, vec![4, 2]);
+        assert_eq!(lines[1].actions, vec![0]);
+        assert_eq!(lines[1].state.board, [1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(tree.best_lines(1, 10)[0].node_id, 6);
+        assert!(tree.best_lines(5, 0).is_empty());
+    }
+
+    #[test]
+    fn test_reroot_transpositions() {
+        let mut tree = setup_tree();
+        let (_, second) = setup_transposition(&mut tree);
+        let state = tree.state(second);
+        tree.expand(second, &state);
+        tree.stats_mut(second).plays = 2.0;
+        let target_id = tree.position(second);
+        tree.stats_mut(target_id).proof = Proof::Draw;
+        tree.stats_mut(target_id).pessimistic = 0.5;
+        // The second line takes over the children of the first, which
+        // isn't kept
+        let root_id = follow(&mut tree, 0, &[1]);
+        tree.reroot(root_id);
+        assert_eq!(tree.root_state.board, [0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        let node_id = follow(&mut tree, 0, &[0, 4]);
+        assert_eq!(tree.get(node_id).link, None);
+        assert_eq!(tree.stats(node_id).plays, 2.0);
+        assert_eq!(tree.stats(node_id).proof, Proof::Draw);
+        assert_eq!(tree.stats(node_id).pessimistic, 0.5);
+        assert_eq!(tree.get(node_id).depth, 2);
+        let children = tree.children(node_id);
+        assert_eq!(children.len(), 6);
+        for child_id in children {
+            assert_eq!(tree.get(child_id).parent, Some(node_id));
+            assert_eq!(tree.get(child_id).depth, 3);
+        }
+    }
+
+    #[test]
+    fn test_state_cache() {
+        for k in 1..4 {
+            let mut tree = setup_tree();
+            tree.state_cache = StateCache::Every(k);
+            tree.add_node(3, 0); // 1 (node id)
+            tree.add_node(0, 1); // 2
+            tree.add_node(1, 2); // 3
+            tree.add_node(2, 3); // 4
+            assert_eq!(tree.cached_state(0).is_some(), false);
+            assert_eq!(tree.cached_state(k).is_some(), true);
+            assert_eq!(tree.cached_state(k + 1).is_some(), k == 1);
+            assert_eq!(tree.state(4).board, [2, 1, 2, 1, 0, 0, 0, 0, 0]);
+            tree.reroot(2);
+            assert_eq!(tree.state(2).board, [2, 1, 2, 1, 0, 0, 0, 0, 0]);
+            assert_eq!(tree.get(2).depth, 2);
+        }
+        let mut tree = setup_tree();
+        tree.state_cache = StateCache::Every(0);
+        tree.add_node(3, 0); // 1
+        assert_eq!(tree.cached_state(1).is_some(), false);
+    }
+
     #[test]
     fn test_unexpanded_actions() {
         let mut tree = setup_tree();
@@ -210,7 +894,7 @@ mod tests {
         tree.add_node(7, 0); // 8
         tree.add_node(8, 0); // 9
         let unexpanded = tree.unexpanded_actions(0, &tree.state(0));
-        assert_eq!(unexpanded, vec![]);
+        assert_eq!(unexpanded, Vec::<usize>::new());
         tree.add_node(0, 3); // 10
         tree.add_node(1, 3); // 11
         tree.add_node(3, 3); // 12
@@ -247,7 +931,6 @@ mod tests {
     }
 
     fn setup_tree() -> NodeTree<TicTacToeState> {
-        let agent = MonteCarloAgent {};
         let mut state = TicTacToeState::new(); // state doesn't matter
         let mut tree = NodeTree::new(&state);
         tree
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::game::*;
+use crate::tree::*;
+use crate::tree_node::*;
+
+// What TreeFormatter shows of each node. Wins, plays and mean are those of
+// the edge from the parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Id,
+    Action,
+    Wins,
+    Plays,
+    Mean,
+    Uct,
+    Parent,
+    Children,
+    // GameState::render of the node's state
+    State,
+}
+
+// Prints a NodeTree as an outline with a line per node. Nodes deeper than
+// max_depth are left out, and so are the children of a node past the first
+// max_children, which are counted on a line of their own. Links to
+// transpositions are shown below the linking node as `-> node`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeFormatter {
+    pub fields: Vec<Field>,
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+    // Most visited children first instead of in the order of legal actions
+    pub sort_by_visits: bool,
+    // Exploration constant of the UCT scores shown, see Ucb1
+    pub c: f64,
+}
+
+impl TreeFormatter {
+
+    pub fn new() -> Self {
+        Self {
+            fields: vec![Field::Id, Field::Action, Field::Wins, Field::Plays],
+            max_depth: None,
+            max_children: None,
+            sort_by_visits: false,
+            c: std::f64::consts::SQRT_2,
+        }
+    }
+
+    pub fn fields(mut self, fields: &[Field]) -> Self {
+        self.fields = fields.to_vec();
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_children(mut self, max_children: usize) -> Self {
+        self.max_children = Some(max_children);
+        self
+    }
+
+    pub fn sort_by_visits(mut self, sort_by_visits: bool) -> Self {
+        self.sort_by_visits = sort_by_visits;
+        self
+    }
+
+    pub fn c(mut self, c: f64) -> Self {
+        self.c = c;
+        self
+    }
+
+    pub fn write<S: GameState>(&self, tree: &NodeTree<S>, out: &mut dyn Write
+                               ) -> io::Result<()> {
+        write!(out, "{}", self.display(tree))
+    }
+
+    pub fn format<S: GameState>(&self, tree: &NodeTree<S>) -> String {
+        self.display(tree).to_string()
+    }
+
+    // The tree formatted by self, for use with format! and friends.
+    pub fn display<'a, S: GameState>(&'a self, tree: &'a NodeTree<S>
+                                     ) -> FormattedTree<'a, S> {
+        FormattedTree {
+            formatter: self,
+            tree: tree,
+        }
+    }
+
+    fn fmt_node<S: GameState>(&self, f: &mut fmt::Formatter,
+                              tree: &NodeTree<S>, node_id: NodeId,
+                              depth: usize, prefix: &str, last: bool
+                              ) -> fmt::Result {
+        let prefix_current = if last { "`- " } else { "|- " };
+        writeln!(f, "{}{}{}", prefix, prefix_current,
+                 self.label(tree, node_id))?;
+        if let Some(link) = tree.get(node_id).link {
+            writeln!(f, "{}   -> {}", prefix, link)?;
+        }
+        if self.max_depth.map_or(false, |max_depth| depth >= max_depth) {
+            return Ok(());
+        }
+
+        let prefix_child = if last { "   " } else { "|  " };
+        let prefix = prefix.to_string() + prefix_child;
+
+        let mut children = tree.get(node_id).children()
+            .collect::<Vec<NodeId>>();
+        if self.sort_by_visits {
+            // Stable, so ties keep the order of legal actions
+            children.sort_by(|&a, &b| {
+                tree.stats(b).plays.total_cmp(&tree.stats(a).plays)
+            });
+        }
+        let shown = self.max_children.unwrap_or(children.len())
+            .min(children.len());
+        let hidden = children.len() - shown;
+        for (i, &child_id) in children[..shown].iter().enumerate() {
+            self.fmt_node(f, tree, child_id, depth + 1, &prefix,
+                          hidden == 0 && i + 1 == shown)?;
+        }
+        if hidden > 0 {
+            writeln!(f, "{}`- ({} more)", prefix, hidden)?;
+        }
+        Ok(())
+    }
+
+    fn label<S: GameState>(&self, tree: &NodeTree<S>, node_id: NodeId
+                           ) -> String {
+        let node = tree.get(node_id);
+        let stats = tree.stats(node_id);
+        self.fields.iter().map(|field| {
+            match field {
+                Field::Id => format!("id:{}", node_id),
+                Field::Action => match &node.action {
+                    Some(action) => format!("action:{:?}", action),
+                    None => "action:root".to_string(),
+                },
+                Field::Wins => format!("wins:{}", stats.wins),
+                Field::Plays => format!("plays:{}", stats.plays),
+                Field::Mean if stats.plays > 0.0 => {
+                    format!("mean:{:.3}", stats.wins / stats.plays)
+                }
+                Field::Mean => "mean:-".to_string(),
+                Field::Uct => match tree.uct(node_id, self.c) {
+                    Some(uct) => format!("uct:{:.3}", uct),
+                    None => "uct:-".to_string(),
+                },
+                Field::Parent => match node.parent {
+                    Some(parent_id) => format!("parent:{}", parent_id),
+                    None => "parent:-".to_string(),
+                },
+                Field::Children => format!("children:{:?}", node.children()),
+                Field::State => {
+                    format!("state:{}", tree.state(node_id).render())
+                }
+            }
+        }).collect::<Vec<String>>().join(" ")
+    }
+}
+
+impl Default for TreeFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// See TreeFormatter::display
+pub struct FormattedTree<'a, S: GameState> {
+    formatter: &'a TreeFormatter,
+    tree: &'a NodeTree<S>,
+}
+
+impl<'a, S: GameState> fmt::Display for FormattedTree<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.formatter.fmt_node(f, self.tree, 0, 0, "", true)
+    }
+}
+
+// Formats with TreeFormatter::new()
+impl<S: GameState> fmt::Display for NodeTree<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        TreeFormatter::new().display(self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tic_tac_toe::*;
+
+    use super::*;
+
+    fn setup_tree() -> NodeTree<TicTacToeState> {
+        let mut tree = NodeTree::new(&TicTacToeState::new());
+        tree.add_node(4, 0); // 1 (node id)
+        tree.add_node(0, 0); // 2
+        tree.add_node(8, 0); // 3
+        tree.add_node(2, 1); // 4
+        for (node_id, wins, plays) in [(0, 0.0, 10.0), (1, 1.0, 2.0),
+                                       (2, 6.0, 8.0), (3, 0.0, 0.0),
+                                       (4, 1.0, 1.0)].iter() {
+            let stats = tree.stats_mut(*node_id);
+            stats.wins = *wins;
+            stats.plays = *plays;
+        }
+        tree
+    }
+
+    #[test]
+    fn test_display() {
+        let tree = setup_tree();
+        assert_eq!(tree.to_string(), "\
+`- id:0 action:root wins:0 plays:10
+   |- id:1 action:4 wins:1 plays:2
+   |  `- id:4 action:2 wins:1 plays:1
+   |- id:2 action:0 wins:6 plays:8
+   `- id:3 action:8 wins:0 plays:0
+");
+        let mut out = Vec::new();
+        TreeFormatter::new().write(&tree, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), tree.to_string());
+    }
+
+    #[test]
+    fn test_formatter() {
+        let tree = setup_tree();
+        let formatter = TreeFormatter::new()
+            .fields(&[Field::Action, Field::Mean, Field::Uct])
+            .sort_by_visits(true)
+            .max_children(2)
+            .c(0.0);
+        assert_eq!(formatter.format(&tree), "\
+`- action:root mean:0.000 uct:-
+   |- action:0 mean:0.750 uct:0.750
+   |- action:4 mean:0.500 uct:0.500
+   |  `- action:2 mean:1.000 uct:1.000
+   `- (1 more)
+");
+        let formatter = TreeFormatter::new()
+            .fields(&[Field::Parent, Field::State])
+            .max_depth(1);
+        let out = formatter.format(&tree);
+        assert!(out.contains("|- parent:0 state:.../.1./... 2\n"));
+        assert!(!out.contains("parent:1"));
+    }
+}
@@ -1,3 +1,8 @@
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum NodeStatus {
     Expandable,
@@ -5,30 +10,154 @@ pub enum NodeStatus {
     Leaf,
 }
 
+// Links and per node data. Statistics live in NodeTree in an array of their
+// own so selection only touches the stats of the children it scores.
 #[derive(Debug, Clone)]
-pub struct Node {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Node<A> {
     pub parent: Option<NodeId>,
-    pub first_child: Option<NodeId>,
-    pub next_sibling: Option<NodeId>,
+    // Children are allocated next to each other, see children()
+    pub first_child: NodeId,
+    pub num_children: usize,
 //     pub status: NodeStatus,
-    pub action: NodeAction,
-    pub wins: f64,
-    pub plays: f64,
+    // None for the root only
+    pub action: Option<A>,
+    // The player who took action, wins are from their point of view
+    pub player: usize,
+    pub depth: usize,
+    // With transpositions, the node already holding the position this
+    // node's action leads to. That node has the children, this one keeps
+    // the statistics of the edge only.
+    pub link: Option<NodeId>,
 }
 
-impl Node {
-    pub fn new(action: NodeAction, parent: Option<NodeId>) -> Self {
+impl<A> Node<A> {
+    pub fn new(action: Option<A>, parent: Option<NodeId>) -> Self {
         Self {
             parent: parent,
-            first_child: None,
-            next_sibling: None,
+            first_child: 0,
+            num_children: 0,
 //             status: NodeStatus::Expandable,
             action: action,
+            player: 0,
+            depth: 0,
+            link: None,
+        }
+    }
+
+    pub fn children(&self) -> Range<NodeId> {
+        self.first_child..self.first_child + self.num_children
+    }
+}
+
+// Game theoretic value of a node once the solver has worked it out.
+// Values are for all players at once: Win(p) is a loss for everyone else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Proof {
+    Unknown,
+    Win(usize),
+    Draw,
+}
+
+impl Proof {
+    // A win for the player strictly ahead of all others, a draw otherwise.
+    pub fn from_rewards(rewards: &[f64]) -> Self {
+        for (player, &reward) in rewards.iter().enumerate() {
+            if rewards.iter().enumerate()
+                .all(|(other, &other_reward)| {
+                    other == player || other_reward < reward
+                }) {
+                return Proof::Win(player);
+            }
+        }
+        Proof::Draw
+    }
+
+    pub fn is_win_for(&self, player: usize) -> bool {
+        *self == Proof::Win(player)
+    }
+
+    pub fn is_loss_for(&self, player: usize) -> bool {
+        matches!(*self, Proof::Win(winner) if winner != player)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeStats {
+    pub wins: f64,
+    pub sq_wins: f64,
+    pub plays: f64,
+    pub prior: f64,
+    pub proof: Proof,
+    // Bounds on the root player's score, see MonteCarloAgent::score_bounds
+    #[cfg_attr(feature = "serde", serde(with = "lower_bound"))]
+    pub pessimistic: f64,
+    #[cfg_attr(feature = "serde", serde(with = "upper_bound"))]
+    pub optimistic: f64,
+    // All-moves-as-first, see MonteCarloAgent::amaf
+    pub amaf_wins: f64,
+    pub amaf_plays: f64,
+}
+
+impl NodeStats {
+    pub fn new() -> Self {
+        Self {
             wins: 0.0,
+            sq_wins: 0.0,
             plays: 0.0,
+            prior: 1.0,
+            proof: Proof::Unknown,
+            pessimistic: f64::NEG_INFINITY,
+            optimistic: f64::INFINITY,
+            amaf_wins: 0.0,
+            amaf_plays: 0.0,
         }
     }
 }
 
 pub type NodeId = usize;
-pub type NodeAction = usize;
+
+// JSON has no infinities, so bounds that aren't known yet are saved as None
+#[cfg(feature = "serde")]
+mod lower_bound {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bound: &f64, serializer: S
+                                    ) -> Result<S::Ok, S::Error> {
+        Some(*bound).filter(|bound| bound.is_finite()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D
+                                                  ) -> Result<f64, D::Error> {
+        Ok(Option::deserialize(deserializer)?.unwrap_or(f64::NEG_INFINITY))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod upper_bound {
+    use serde::{Deserialize, Deserializer};
+
+    pub use super::lower_bound::serialize;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D
+                                                  ) -> Result<f64, D::Error> {
+        Ok(Option::deserialize(deserializer)?.unwrap_or(f64::INFINITY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_from_rewards() {
+        assert_eq!(Proof::from_rewards(&[0.0, 1.0, 0.0]), Proof::Win(1));
+        assert_eq!(Proof::from_rewards(&[0.0, 0.5, 0.5]), Proof::Draw);
+        assert_eq!(Proof::from_rewards(&[0.2, 0.7, 0.1]), Proof::Win(1));
+        assert!(Proof::Win(1).is_loss_for(2));
+        assert!(!Proof::Win(1).is_loss_for(1));
+        assert!(!Proof::Draw.is_loss_for(1));
+    }
+}